@@ -0,0 +1,399 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// One entry of an asar's JSON index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Node {
+    Dir {
+        files: BTreeMap<String, Node>,
+    },
+    File {
+        size: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        unpacked: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        executable: bool,
+    },
+    Link {
+        link: String,
+    },
+}
+
+/// An opened asar archive. Only the header is read up front.
+#[derive(Debug)]
+pub struct Asar {
+    pub path: PathBuf,
+    pub root: Node,
+    /// Where file contents start, every `offset` is relative to it.
+    pub data_offset: u64,
+}
+
+impl Asar {
+    /// Parses the pickled header: a 4 byte size pickle holding the header
+    /// pickle's size, then the header pickle holding the JSON index.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = fs::File::open(path)?;
+        let mut size_pickle = [0u8; 8];
+        file.read_exact(&mut size_pickle)?;
+        if u32::from_le_bytes(size_pickle[0..4].try_into()?) != 4 {
+            return Err(format!("{:?} is not an asar archive", path).into());
+        }
+        let header_size = u32::from_le_bytes(size_pickle[4..8].try_into()?) as u64;
+        // checked before allocating for it, the header is untrusted
+        if header_size < 8 || 8 + header_size > file.metadata()?.len() {
+            return Err(format!("{:?} has a bad asar header size", path).into());
+        }
+
+        let mut header = vec![0u8; header_size as usize];
+        file.read_exact(&mut header)?;
+        let json_size = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+        if 8 + json_size > header.len() {
+            return Err(format!("{:?} has a truncated asar header", path).into());
+        }
+        let root: Node = serde_json::from_slice(&header[8..8 + json_size])?;
+        if !matches!(root, Node::Dir { .. }) {
+            return Err(format!("{:?} has no root directory in its index", path).into());
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            root,
+            data_offset: 8 + header_size,
+        })
+    }
+
+    /// Looks up a `/` separated path inside the archive.
+    pub fn get(&self, path: &str) -> Option<&Node> {
+        let mut node = &self.root;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            node = match node {
+                Node::Dir { files } => files.get(part)?,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    /// Reads a file out of the archive, following it into `<archive>.unpacked`
+    /// if it was left outside.
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self.get(path) {
+            Some(Node::File { unpacked: true, .. }) => {
+                let mut unpacked = self.path.as_os_str().to_owned();
+                unpacked.push(".unpacked");
+                Ok(fs::read(PathBuf::from(unpacked).join(path))?)
+            }
+            Some(Node::File {
+                size,
+                offset: Some(offset),
+                ..
+            }) => {
+                let mut file = fs::File::open(&self.path)?;
+                let len = file.metadata()?.len();
+                let start = self.data_offset.saturating_add(offset.parse::<u64>()?);
+                // checked before allocating for it, the index is untrusted
+                if !matches!(start.checked_add(*size), Some(end) if end <= len) {
+                    return Err(format!("{} lies past the end of {:?}", path, self.path).into());
+                }
+                file.seek(SeekFrom::Start(start))?;
+                let mut buf = vec![0u8; *size as usize];
+                file.read_exact(&mut buf)?;
+                Ok(buf)
+            }
+            Some(_) => Err(format!("{} is not a file in {:?}", path, self.path).into()),
+            None => Err(format!("{} not found in {:?}", path, self.path).into()),
+        }
+    }
+
+    /// Every entry in the archive with its `/` separated path, parents
+    /// before their children.
+    pub fn entries(&self) -> Vec<(String, &Node)> {
+        fn walk<'a>(prefix: &str, node: &'a Node, out: &mut Vec<(String, &'a Node)>) {
+            if let Node::Dir { files } = node {
+                for (name, child) in files {
+                    let path = if prefix.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    };
+                    out.push((path.clone(), child));
+                    walk(&path, child, out);
+                }
+            }
+        }
+        let mut out = vec![];
+        walk("", &self.root, &mut out);
+        out
+    }
+
+    /// Unpacks the whole archive, `.unpacked` files included, into `dest`.
+    pub fn extract(&self, dest: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(dest)?;
+        let mut links = vec![];
+        for (path, node) in self.entries() {
+            // the index is untrusted, nothing in it may point outside `dest`
+            let relative = Path::new(&path);
+            if !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            {
+                return Err(format!("{:?} has an unsafe path {:?}", self.path, path).into());
+            }
+            let target = dest.join(relative);
+            match node {
+                Node::Dir { .. } => fs::create_dir_all(&target)?,
+                Node::File { executable, .. } => {
+                    fs::write(&target, self.read(&path)?)?;
+                    #[cfg(unix)]
+                    if *executable {
+                        use std::os::unix::fs::PermissionsExt;
+                        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+                    }
+                    #[cfg(not(unix))]
+                    let _ = executable;
+                }
+                Node::Link { link } => {
+                    if !Path::new(link)
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_)))
+                    {
+                        return Err(format!("{:?} has an unsafe link {:?}", self.path, link).into());
+                    }
+                    links.push((relative.to_path_buf(), target, link));
+                }
+            }
+        }
+
+        // made last, so whatever they point to is already there
+        for (relative, target, link) in links {
+            #[cfg(unix)]
+            {
+                // links are relative to the archive root, the filesystem
+                // wants them relative to the link's folder
+                let mut to = PathBuf::new();
+                for _ in 1..relative.components().count() {
+                    to.push("..");
+                }
+                to.push(link);
+                std::os::unix::fs::symlink(&to, &target)?;
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = relative;
+                let from = dest.join(link);
+                if from.is_dir() {
+                    crate::platform::copy_dir(&from, &target)?;
+                } else {
+                    fs::copy(&from, &target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs the folder `src` into a new archive at `dest`. Symlinks that
+    /// stay inside `src` are kept as links, anything else is followed, unless
+    /// it leads back into a folder being packed.
+    pub fn pack(src: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+        let src = fs::canonicalize(src)?;
+        let mut files = vec![];
+        let mut offset = 0u64;
+        let root = Self::index(&src, &src, &mut vec![], &mut files, &mut offset)?;
+
+        let json = serde_json::to_vec(&root)?;
+        let padding = (4 - json.len() % 4) % 4;
+        let payload_size = 4 + json.len() + padding;
+        let header_size = 4 + payload_size;
+
+        let tmp = dest.with_extension("asar.tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        out.write_all(&4u32.to_le_bytes())?;
+        out.write_all(&(header_size as u32).to_le_bytes())?;
+        out.write_all(&(payload_size as u32).to_le_bytes())?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(&json)?;
+        out.write_all(&vec![0u8; padding])?;
+        for file in files {
+            io::copy(&mut fs::File::open(file)?, &mut out)?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(tmp, dest)?;
+        Ok(())
+    }
+
+    /// Builds the index for `dir`, queueing file contents into `files` in
+    /// the order their offsets were handed out. `parents` are the folders
+    /// `dir` was reached through, a symlink back to one would never end.
+    fn index(
+        root: &Path,
+        dir: &Path,
+        parents: &mut Vec<PathBuf>,
+        files: &mut Vec<PathBuf>,
+        offset: &mut u64,
+    ) -> Result<Node, Box<dyn Error>> {
+        let real = fs::canonicalize(dir)?;
+        if parents.contains(&real) {
+            return Err(format!("{:?} links back to {:?}, can't pack a loop", dir, real).into());
+        }
+        parents.push(real);
+        let mut entries = BTreeMap::new();
+        let mut children = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .collect::<Vec<_>>();
+        children.sort_by_key(|e| e.file_name());
+        for child in children {
+            let name = child.file_name().to_string_lossy().to_string();
+            let path = child.path();
+            if child.file_type()?.is_symlink() {
+                if let Some(link) = fs::canonicalize(&path)
+                    .ok()
+                    .and_then(|target| target.strip_prefix(root).ok().map(|t| t.to_path_buf()))
+                {
+                    let link = link
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    entries.insert(name, Node::Link { link });
+                    continue;
+                }
+            }
+            let metadata = fs::metadata(&path)?;
+            let node = if metadata.is_dir() {
+                Self::index(root, &path, parents, files, offset)?
+            } else {
+                #[cfg(unix)]
+                let executable = {
+                    use std::os::unix::fs::PermissionsExt;
+                    metadata.permissions().mode() & 0o111 != 0
+                };
+                #[cfg(not(unix))]
+                let executable = false;
+                let node = Node::File {
+                    size: metadata.len(),
+                    offset: Some(offset.to_string()),
+                    unpacked: false,
+                    executable,
+                };
+                *offset += metadata.len();
+                files.push(path);
+                node
+            };
+            entries.insert(name, node);
+        }
+        parents.pop();
+        Ok(Node::Dir { files: entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::test_dir;
+
+    fn folder(dir: &Path) {
+        fs::create_dir_all(dir.join("lib/deep")).unwrap();
+        fs::write(dir.join("package.json"), r#"{"main":"index.js"}"#).unwrap();
+        fs::write(dir.join("index.js"), "require('./lib/deep/a.js')").unwrap();
+        fs::write(dir.join("lib/deep/a.js"), vec![7u8; 1000]).unwrap();
+        fs::write(dir.join("lib/empty"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("deep/a.js", dir.join("lib/link.js")).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = test_dir("asar-round-trip");
+        folder(&dir.join("src"));
+        let archive = dir.join("app.asar");
+        Asar::pack(&dir.join("src"), &archive).unwrap();
+
+        let asar = Asar::open(&archive).unwrap();
+        let paths = asar
+            .entries()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        for path in [
+            "index.js",
+            "lib",
+            "lib/deep",
+            "lib/deep/a.js",
+            "lib/empty",
+            "package.json",
+        ] {
+            assert!(paths.contains(&path.to_string()), "{} missing", path);
+        }
+        assert_eq!(asar.read("lib/deep/a.js").unwrap(), vec![7u8; 1000]);
+        assert_eq!(asar.read("lib/empty").unwrap(), b"");
+        #[cfg(unix)]
+        assert!(
+            matches!(asar.get("lib/link.js"), Some(Node::Link { link }) if link == "lib/deep/a.js")
+        );
+
+        asar.extract(&dir.join("out")).unwrap();
+        for file in ["package.json", "index.js", "lib/deep/a.js", "lib/empty"] {
+            assert_eq!(
+                fs::read(dir.join("out").join(file)).unwrap(),
+                fs::read(dir.join("src").join(file)).unwrap()
+            );
+        }
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read(dir.join("out/lib/link.js")).unwrap(),
+            vec![7u8; 1000]
+        );
+    }
+
+    /// A size pickle for a header claiming `header_size`, followed by `rest`.
+    fn crafted(dir: &Path, header_size: u32, rest: &[u8]) -> PathBuf {
+        let path = dir.join(format!("crafted-{}.asar", header_size));
+        let mut bytes = 4u32.to_le_bytes().to_vec();
+        bytes.extend(header_size.to_le_bytes());
+        bytes.extend(rest);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let dir = test_dir("asar-oversized-header");
+        assert!(Asar::open(&crafted(&dir, u32::MAX, &[0; 16])).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_file() {
+        let dir = test_dir("asar-oversized-file");
+        let json = format!(
+            r#"{{"files":{{"a":{{"size":{},"offset":"0"}}}}}}"#,
+            u64::MAX / 2
+        );
+        let padding = (4 - json.len() % 4) % 4;
+        let mut header = ((4 + json.len() + padding) as u32).to_le_bytes().to_vec();
+        header.extend((json.len() as u32).to_le_bytes());
+        header.extend(json.as_bytes());
+        header.extend(vec![0; padding]);
+        let asar = Asar::open(&crafted(&dir, header.len() as u32, &header)).unwrap();
+        assert!(asar.read("a").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pack_refuses_loops() {
+        let dir = test_dir("asar-loop");
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("outside/again")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("src/outside")).unwrap();
+        assert!(Asar::pack(&dir.join("src"), &dir.join("loop.asar")).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use log as l;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::discord::download_openasar;
+use crate::http;
+use crate::lock::Lock;
+use crate::moonlight::{Channel, Published};
+use crate::platform::{sha256_bytes, sha256_file};
+use crate::Platform;
+
+/// Bumped whenever the layout changes, so moon refuses bundles it can't read.
+const FORMAT: u32 = 1;
+
+const EXTENSIONS_REPO: &str = "https://moonlight-mod.github.io/extensions-dist/repo.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Extension {
+    pub id: String,
+    pub version: Option<String>,
+    pub sha256: String,
+}
+
+/// `manifest.json` in a bundle: what it holds and the hashes to check it by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub channel: Channel,
+    pub version: String,
+    /// Of dist.tar.gz, as verified against what moonlight published.
+    pub sha256: String,
+    /// Of openasar/app.asar, if OpenAsar was bundled.
+    pub openasar: Option<String>,
+    /// Each kept as `extensions/<id>.asar`.
+    pub extensions: Vec<Extension>,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+}
+
+#[inline(always)]
+fn append(
+    builder: &mut tar::Builder<fs::File>,
+    name: &str,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Downloads the `id` extension from moonlight's extension repo.
+async fn fetch_extension(
+    repo: &[serde_json::Value],
+    id: &str,
+) -> Result<(Extension, Vec<u8>), Box<dyn Error>> {
+    let entry = repo
+        .iter()
+        .find(|e| e.get("id").and_then(|i| i.as_str()) == Some(id))
+        .ok_or_else(|| format!("No extension {} in {}", id, EXTENSIONS_REPO))?;
+    let url = entry
+        .get("download")
+        .and_then(|d| d.as_str())
+        .ok_or_else(|| format!("Extension {} has no download", id))?;
+    l::info!("Downloading extension {}...", id);
+    let asar = http::get(url)
+        .await?
+        .bytes()
+        .await?
+        .to_vec();
+    let extension = Extension {
+        id: id.to_string(),
+        version: entry
+            .get("version")
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string()),
+        sha256: sha256_bytes(&asar),
+    };
+    Ok((extension, asar))
+}
+
+/// Writes a bundle of everything `moon up --bundle` needs to `output`: the
+/// verified dist tarball for `channel`, and OpenAsar and `extensions` if asked.
+pub async fn fetch(
+    channel: Channel,
+    version: Option<&str>,
+    openasar: bool,
+    extensions: &[String],
+    output: &Path,
+) -> Result<Manifest, Box<dyn Error>> {
+    let published = Published::resolve(channel, version).await?;
+    l::info!(
+        "Downloading moonlight ({:?} {})...",
+        published.channel,
+        published.version
+    );
+    let (tarball, sha256) = published.fetch().await?;
+
+    // downloaded on the side, the OpenAsar `moon openasar` keeps is left alone
+    let openasar = if openasar {
+        let tmp = std::env::temp_dir().join(format!("moon-openasar-{}.asar", std::process::id()));
+        let read = download_openasar(&tmp)
+            .await
+            .and_then(|_| Ok(fs::read(&tmp)?));
+        let _ = fs::remove_file(&tmp);
+        Some(read?)
+    } else {
+        None
+    };
+
+    let mut fetched = vec![];
+    if !extensions.is_empty() {
+        let repo: Vec<serde_json::Value> = serde_json::from_str(
+            &http::get(EXTENSIONS_REPO)
+                .await?
+                .text()
+                .await?,
+        )?;
+        for id in extensions {
+            fetched.push(fetch_extension(&repo, id).await?);
+        }
+    }
+
+    let manifest = Manifest {
+        format: FORMAT,
+        channel: published.channel,
+        version: published.version.clone(),
+        sha256: sha256.clone(),
+        openasar: openasar.as_deref().map(sha256_bytes),
+        extensions: fetched.iter().map(|(e, _)| e.clone()).collect(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    };
+
+    // written beside the output and renamed, so a failed fetch leaves no
+    // half written bundle to be carried to another machine
+    let mut tmp = output.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut builder = tar::Builder::new(fs::File::create(&tmp)?);
+    let write = |builder: &mut tar::Builder<fs::File>| -> Result<(), Box<dyn Error>> {
+        append(
+            builder,
+            "manifest.json",
+            &serde_json::to_vec_pretty(&manifest)?,
+        )?;
+        append(builder, "dist.tar.gz", &tarball)?;
+        append(
+            builder,
+            "dist.tar.gz.sha256",
+            format!("{}  dist.tar.gz\n", sha256).as_bytes(),
+        )?;
+        if let Some(openasar) = &openasar {
+            append(builder, "openasar/app.asar", openasar)?;
+        }
+        for (extension, asar) in &fetched {
+            append(builder, &format!("extensions/{}.asar", extension.id), asar)?;
+        }
+        builder.finish()?;
+        Ok(())
+    };
+    if let Err(e) = write(&mut builder) {
+        drop(builder);
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    drop(builder);
+    fs::rename(&tmp, output)?;
+    Ok(manifest)
+}
+
+/// A bundle unpacked into moon's data dir, removed again when dropped.
+/// Everything in it has been checked against its manifest.
+pub struct Bundle {
+    pub manifest: Manifest,
+    dir: PathBuf,
+    /// Held while it's unpacked, so other moon runs can tell it's in use.
+    _lock: Lock,
+}
+
+impl Bundle {
+    #[inline(always)]
+    fn dir() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("bundles")
+    }
+
+    /// Removes bundles left unpacked by moon runs that didn't get to clean
+    /// up after themselves. One still locked is in use by a run going on.
+    fn clear_stale() -> Result<(), Box<dyn Error>> {
+        if !Self::dir().exists() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(Self::dir())?.filter_map(|e| e.ok()) {
+            let stale = entry.path();
+            if let Some(_lock) = Lock::try_acquire(&stale)? {
+                l::debug!("Removing stale unpacked bundle {:?}", stale);
+                if let Err(e) = fs::remove_dir_all(&stale) {
+                    l::warn!("Failed to remove the stale bundle at {:?}: {}", stale, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::clear_stale()?;
+        let dir = Self::dir().join(std::process::id().to_string());
+        fs::create_dir_all(&dir)?;
+        let lock = Lock::try_acquire(&dir)?.ok_or_else(|| format!("{:?} is in use", dir))?;
+        // until it's a Bundle, whose Drop cleans up, a failure cleans up here
+        let bundle = |dir: PathBuf| -> Result<Self, Box<dyn Error>> {
+            let mut archive = tar::Archive::new(fs::File::open(path)?);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_path_buf();
+                if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+                    return Err(format!("{:?} has an entry outside of it: {:?}", path, name).into());
+                }
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                entry.unpack_in(&dir)?;
+            }
+            let manifest: Manifest = serde_json::from_str(
+                &fs::read_to_string(dir.join("manifest.json"))
+                    .map_err(|_| format!("{:?} isn't a moon bundle, it has no manifest", path))?,
+            )?;
+            Ok(Self {
+                manifest,
+                dir,
+                _lock: lock,
+            })
+        };
+        let bundle = bundle(dir.clone()).inspect_err(|_| {
+            let _ = fs::remove_dir_all(&dir);
+        })?;
+
+        if bundle.manifest.format > FORMAT {
+            return Err(format!(
+                "{:?} was made by a newer moon (format {}), update moon to use it",
+                path, bundle.manifest.format
+            )
+            .into());
+        }
+        let mut expected = vec![(bundle.dist(), bundle.manifest.sha256.clone())];
+        if let Some(sha256) = &bundle.manifest.openasar {
+            expected.push((bundle.dir.join("openasar/app.asar"), sha256.clone()));
+        }
+        for extension in &bundle.manifest.extensions {
+            let mut parts = Path::new(&extension.id).components();
+            if !matches!(
+                (parts.next(), parts.next()),
+                (Some(Component::Normal(_)), None)
+            ) {
+                return Err(
+                    format!("{:?} lists an invalid extension {:?}", path, extension.id).into(),
+                );
+            }
+            expected.push((bundle.extension(&extension.id), extension.sha256.clone()));
+        }
+        for (file, sha256) in expected {
+            if !file.is_file() || sha256_file(&file)? != sha256 {
+                return Err(format!(
+                    "{:?} in {:?} is missing or doesn't match its manifest",
+                    file.strip_prefix(&bundle.dir).unwrap_or(&file),
+                    path
+                )
+                .into());
+            }
+        }
+        l::info!(
+            "Opened bundle {:?}: moonlight {:?} {}{}, {} extensions",
+            path,
+            bundle.manifest.channel,
+            bundle.manifest.version,
+            if bundle.manifest.openasar.is_some() {
+                ", OpenAsar"
+            } else {
+                ""
+            },
+            bundle.manifest.extensions.len()
+        );
+        Ok(bundle)
+    }
+
+    /// The dist tarball, with its dist.tar.gz.sha256 beside it.
+    #[inline(always)]
+    pub fn dist(&self) -> PathBuf {
+        self.dir.join("dist.tar.gz")
+    }
+
+    #[inline(always)]
+    pub fn openasar(&self) -> Option<PathBuf> {
+        self.manifest
+            .openasar
+            .as_ref()
+            .map(|_| self.dir.join("openasar/app.asar"))
+    }
+
+    #[inline(always)]
+    pub fn extension(&self, id: &str) -> PathBuf {
+        self.dir.join("extensions").join(format!("{}.asar", id))
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            l::warn!(
+                "Failed to remove the unpacked bundle at {:?}: {}",
+                self.dir,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use crate::{
+    cli::args::adopt::Args,
+    discord::{DiscordKind, InjectorFormat},
+};
+
+use log as l;
+
+use super::lock_installs;
+
+#[inline(always)]
+pub async fn adopt(args: Args) {
+    let kind = DiscordKind::from(args.branch.clone());
+    // held from planning until it has run
+    let (_locks, installs) = match lock_installs(&args.branch, false).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't adopt: {}", e);
+            return;
+        }
+    };
+    let install = match installs.first() {
+        Some(install) => install,
+        None => {
+            l::error!("No Discord install found for branch {:?}", kind);
+            return;
+        }
+    };
+    let injector = match install.injector() {
+        Some(injector) => injector,
+        None => {
+            l::error!("Discord {:?} isn't injected with moonlight, use moon up instead", kind);
+            return;
+        }
+    };
+    if injector.format == InjectorFormat::Moon && args.root.is_none() {
+        l::info!("Discord {:?} is already managed by moon", kind);
+        return;
+    }
+    l::info!(
+        "Found a {:?} injection loading {:?}",
+        injector.format,
+        injector.target
+    );
+    let root = args.root.map(PathBuf::from);
+
+    l::info!("Killing discord...");
+    while install.kill().await.is_ok() {}
+    l::info!("Adopting...");
+    if let Err(e) = install.adopt(root.as_ref()).await {
+        l::error!("Failed to adopt: {}", e);
+        return;
+    };
+    l::info!("Done!");
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use crate::{
+    asar::{Asar, Node},
+    cli::args::asar::Args,
+};
+
+use log as l;
+
+#[inline(always)]
+pub async fn asar(args: Args) {
+    let source = PathBuf::from(&args.source);
+    let dest = args.dest.map(PathBuf::from).unwrap_or_default();
+
+    if args.action == "pack" {
+        if let Err(e) = Asar::pack(&source, &dest) {
+            l::error!("Failed to pack {:?}: {}", source, e);
+            return;
+        }
+        l::info!("Packed {:?} into {:?}", source, dest);
+        return;
+    }
+
+    let archive = match Asar::open(&source) {
+        Ok(archive) => archive,
+        Err(e) => {
+            l::error!("Failed to open {:?}: {}", source, e);
+            return;
+        }
+    };
+    match args.action.as_str() {
+        "list" => {
+            for (path, node) in archive.entries() {
+                match node {
+                    Node::Dir { .. } => println!("{}/", path),
+                    Node::File { size, unpacked, .. } => println!(
+                        "{} ({} bytes{})",
+                        path,
+                        size,
+                        if *unpacked { ", unpacked" } else { "" }
+                    ),
+                    Node::Link { link } => println!("{} -> {}", path, link),
+                }
+            }
+        }
+        "extract" => {
+            if let Err(e) = archive.extract(&dest) {
+                l::error!("Failed to extract {:?}: {}", source, e);
+                return;
+            }
+            l::info!("Extracted {:?} into {:?}", source, dest);
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use crate::{
+    cli::args::config::Args,
+    config::{Branch, Config, Network},
+    discord::DiscordKind,
+    moonlight::Channel,
+};
+
+use log as l;
+
+#[inline(always)]
+fn show(config: &Config, kind: DiscordKind) {
+    let branch = config.branch(kind);
+    l::info!(
+        "  {}: channel {:?}{}, version {}, root {:?}{}",
+        kind.branch_name(),
+        config.channel(kind).unwrap_or(Channel::Stable),
+        if branch.channel.is_none() { " (default)" } else { "" },
+        branch.version.as_deref().unwrap_or("latest"),
+        config.root(kind),
+        if branch.root.is_none() { " (default)" } else { "" },
+    );
+}
+
+#[inline(always)]
+fn show_network(network: &Network) {
+    let default = |set: &Option<String>| if set.is_none() { " (default)" } else { "" };
+    l::info!(
+        "  releases from {}{}",
+        network.repo(),
+        default(&network.repo)
+    );
+    l::info!(
+        "  GitHub API at {}{}",
+        network.github_api(),
+        default(&network.github_api)
+    );
+    l::info!(
+        "  nightly from {}{}",
+        network.nightly_url(),
+        default(&network.nightly_url)
+    );
+    l::info!(
+        "  git clones {}{}",
+        network.git_url(),
+        default(&network.git_url)
+    );
+    l::info!("  proxy {}", network.proxy.as_deref().unwrap_or("none"));
+    l::info!(
+        "  GitHub token {}",
+        match (network.github_token(), &network.github_token) {
+            (None, _) => "not set",
+            (Some(token), Some(saved)) if token == *saved => "saved in the config",
+            (Some(_), _) => "from the environment",
+        }
+    );
+    for cert in &network.ca_certs {
+        l::info!("  trusting CA {:?}", cert);
+    }
+}
+
+#[inline(always)]
+pub async fn config(args: Args) {
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            l::error!("Failed to read {:?}: {}", Config::path(), e);
+            return;
+        }
+    };
+
+    let setting = |value: Option<String>, current: &mut Option<String>| {
+        if let Some(value) = value {
+            *current = (!value.is_empty()).then_some(value);
+        }
+    };
+    let mut network = config.network.clone();
+    setting(args.repo, &mut network.repo);
+    setting(args.github_api, &mut network.github_api);
+    setting(args.nightly_url, &mut network.nightly_url);
+    setting(args.git_url, &mut network.git_url);
+    setting(args.proxy, &mut network.proxy);
+    setting(args.github_token, &mut network.github_token);
+    if let Some(certs) = args.ca_certs {
+        network.ca_certs = vec![];
+        for cert in certs.into_iter().filter(|cert| !cert.is_empty()) {
+            match std::path::absolute(PathBuf::from(&cert)) {
+                Ok(cert) => network.ca_certs.push(cert),
+                Err(e) => {
+                    l::error!("Invalid CA certificate path {}: {}", cert, e);
+                    return;
+                }
+            }
+        }
+    }
+    if let Some(repo) = network
+        .repo
+        .as_deref()
+        .filter(|repo| repo.split('/').count() != 2)
+    {
+        l::error!("{} isn't an owner/repo", repo);
+        return;
+    }
+    if args.keep_versions.is_some() || network != config.network {
+        config.keep_versions = args.keep_versions.or(config.keep_versions);
+        config.network = network;
+        if let Err(e) = config.save() {
+            l::error!("Failed to write {:?}: {}", Config::path(), e);
+            return;
+        }
+    }
+
+    let kind = match args.branch {
+        Some(branch) => DiscordKind::from(branch),
+        None => {
+            l::info!("Settings from {:?}:", Config::path());
+            for kind in DiscordKind::ALL {
+                show(&config, kind);
+            }
+            l::info!(
+                "  each root keeps {} old moonlight versions",
+                config.keep_versions()
+            );
+            show_network(&config.network);
+            return;
+        }
+    };
+
+    if args.reset || args.channel.is_some() || args.root.is_some() {
+        let mut branch = if args.reset {
+            Branch::default()
+        } else {
+            config.branch(kind)
+        };
+        if let Some(channel) = args.channel {
+            branch.channel = Some(Channel::from(channel));
+        }
+        if let Some(root) = args.root {
+            branch.root = match std::path::absolute(PathBuf::from(&root)) {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    l::error!("Invalid root {}: {}", root, e);
+                    return;
+                }
+            };
+        }
+        config.set_branch(kind, branch);
+
+        let root = config.root(kind);
+        if let Some(other) = DiscordKind::ALL.into_iter().find(|other| {
+            *other != kind
+                && config.root(*other) == root
+                && (config.channel(*other).unwrap_or(Channel::Stable)
+                    != config.channel(kind).unwrap_or(Channel::Stable)
+                    || config.version(*other) != config.version(kind))
+        }) {
+            l::error!(
+                "{} would share {:?} with {} on another channel or version, give it its own --root",
+                kind.branch_name(),
+                root,
+                other.branch_name()
+            );
+            return;
+        }
+        if let Err(e) = config.save() {
+            l::error!("Failed to write {:?}: {}", Config::path(), e);
+            return;
+        }
+        l::info!("Run moon up -b {} to apply", kind.branch_name());
+    }
+    show(&config, kind);
+}
@@ -0,0 +1,392 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    cli::args::doctor::Args,
+    config::{Config, Network},
+    discord::{DiscordInstall, DiscordKind},
+    github::{self, RateLimited},
+    http,
+    moonlight::verify_dist,
+    platform::{env, Platform},
+};
+
+use log as l;
+
+/// Dist plus a git checkout and node_modules fit comfortably in this.
+const MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+struct Diagnosis {
+    name: String,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Diagnosis {
+    #[inline(always)]
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    #[inline(always)]
+    fn warn(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    #[inline(always)]
+    fn fail(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    #[inline(always)]
+    fn print(&self) {
+        match self.status {
+            Status::Pass => l::info!("  [pass] {}: {}", self.name, self.detail),
+            Status::Warn => l::warn!("  [warn] {}: {}", self.name, self.detail),
+            Status::Fail => l::error!("  [fail] {}: {}", self.name, self.detail),
+        }
+        if let Some(fix) = &self.fix {
+            l::info!("         fix: {}", fix);
+        }
+    }
+}
+
+#[inline(always)]
+fn check_user() -> Diagnosis {
+    if let Err(e) = Platform::pretransaction_checks() {
+        return Diagnosis::fail("user", e, "Run moon as your normal user, without sudo/doas");
+    }
+    if Platform::is_root() {
+        return Diagnosis::fail(
+            "user",
+            "moon is running as root, Discord's files would end up owned by root",
+            "Run moon as your normal user, without sudo/doas",
+        );
+    }
+    Diagnosis::pass("user", "not running as root")
+}
+
+#[inline(always)]
+fn check_install(install: &DiscordInstall, root: &PathBuf) -> Vec<Diagnosis> {
+    let name = format!("{:?}", install.kind);
+    let mut diagnoses = vec![];
+
+    let state = if install.injected {
+        match install.injector_target() {
+            Some(target) if target.is_file() => {
+                Diagnosis::pass(&name, format!("injected, loads {:?}", target))
+            }
+            Some(target) => Diagnosis::fail(
+                &name,
+                format!("injected, but {:?} doesn't exist", target),
+                format!("moon verify -b {} --repair", install.kind.branch_name()),
+            ),
+            None => Diagnosis::warn(
+                &name,
+                "injected, but not by moon",
+                format!("moon up -b {}", install.kind.branch_name()),
+            ),
+        }
+    } else {
+        Diagnosis::warn(
+            &name,
+            format!(
+                "found at {:?} (host {}), not injected",
+                install.path,
+                install.host_version.as_deref().unwrap_or("unknown")
+            ),
+            format!("moon up -b {}", install.kind.branch_name()),
+        )
+    };
+    diagnoses.push(state);
+
+    for foreign in &install.foreign {
+        diagnoses.push(if foreign.chained {
+            Diagnosis::pass(
+                format!("{} mods", name),
+                format!("{:?} is chain-loaded through moonlight", foreign.name),
+            )
+        } else {
+            Diagnosis::warn(
+                format!("{} mods", name),
+                format!("{:?} is installed via {:?}", foreign.name, foreign.hook),
+                format!(
+                    "moon up -b {} --foreign uninstall, or --foreign chain to keep it",
+                    install.kind.branch_name()
+                ),
+            )
+        });
+    }
+
+    if let Some(version) = &install.openasar_version {
+        diagnoses.push(Diagnosis::pass(
+            format!("{} OpenAsar", name),
+            format!("OpenAsar {} replaces Discord's asar, moonlight loads on top", version),
+        ));
+    }
+
+    diagnoses.push(if install.is_running() {
+        Diagnosis::warn(
+            format!("{} process", name),
+            "running, moon will kill it before touching its files",
+            "Close Discord first if you have unsent messages",
+        )
+    } else {
+        Diagnosis::pass(format!("{} process", name), "not running")
+    });
+
+    let resources = install.resources_dir();
+    let probe = resources.join(".moon-write-test");
+    diagnoses.push(match std::fs::write(&probe, b"") {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Diagnosis::pass(format!("{} resources", name), format!("{:?} is writable", resources))
+        }
+        Err(e) => Diagnosis::fail(
+            format!("{} resources", name),
+            format!("{:?} is not writable: {}", resources, e),
+            "Make sure your user owns Discord's install folder",
+        ),
+    });
+
+    #[cfg(target_os = "linux")]
+    if install.flatpak != crate::discord::Flatpak::Not {
+        diagnoses.push(check_flatpak(install, root));
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = root;
+
+    diagnoses
+}
+
+/// Flatpak Discord can only load moonlight if the sandbox can see the root.
+#[cfg(target_os = "linux")]
+#[inline(always)]
+fn check_flatpak(install: &DiscordInstall, root: &PathBuf) -> Diagnosis {
+    use crate::discord::Flatpak;
+    let name = format!("{:?} sandbox", install.kind);
+    let user = install.flatpak == Flatpak::User;
+    let mut permissions = String::new();
+    for cmd in [
+        vec!["flatpak", "info", "--show-permissions", "com.discordapp.Discord"],
+        vec!["flatpak", "override", "--user", "--show", "com.discordapp.Discord"],
+    ] {
+        let mut cmd = cmd.into_iter().map(|s| s.to_owned()).collect::<Vec<_>>();
+        if user && cmd[1] == "info" {
+            cmd.insert(2, "--user".to_owned());
+        }
+        permissions.push_str(&Platform::cmd_output(cmd, None).unwrap_or_default());
+    }
+    let home = env("HOME", "~/");
+    let visible = permissions
+        .lines()
+        .filter_map(|line| line.strip_prefix("filesystems="))
+        .flat_map(|line| line.split(';'))
+        .map(|fs| fs.split(':').next().unwrap_or_default().replace('~', &home))
+        .any(|fs| match fs.as_str() {
+            "host" => true,
+            "home" => root.starts_with(&home),
+            "" => false,
+            path => root.starts_with(path),
+        });
+    if visible {
+        Diagnosis::pass(name, format!("the sandbox can read {:?}", root))
+    } else {
+        Diagnosis::fail(
+            name,
+            format!("the sandbox can't read {:?}", root),
+            format!(
+                "flatpak override --user --filesystem={}:ro com.discordapp.Discord",
+                root.to_string_lossy()
+            ),
+        )
+    }
+}
+
+#[inline(always)]
+fn check_root(root: &PathBuf) -> Vec<Diagnosis> {
+    let mut diagnoses = vec![];
+    diagnoses.push(match verify_dist(root) {
+        Ok((channel, version)) => {
+            Diagnosis::pass("moonlight", format!("{:?} {} at {:?}", channel, version, root))
+        }
+        Err(e) => Diagnosis::warn("moonlight", e, "moon up"),
+    });
+    diagnoses.push(match Platform::free_space(root) {
+        Some(free) if free >= MIN_FREE_SPACE => Diagnosis::pass(
+            "disk space",
+            format!("{} MiB free", free / 1024 / 1024),
+        ),
+        Some(free) => Diagnosis::warn(
+            "disk space",
+            format!("only {} MiB free near {:?}", free / 1024 / 1024, root),
+            "Free up some space or move the root with MOONLIGHT_ROOT",
+        ),
+        None => Diagnosis::warn(
+            "disk space",
+            "couldn't tell how much space is free",
+            "Check the disk holding the moonlight root isn't full",
+        ),
+    });
+    diagnoses
+}
+
+#[inline(always)]
+async fn check_network(network: &Network) -> Vec<Diagnosis> {
+    let client = match http::builder(network)
+        .and_then(|builder| Ok(builder.timeout(Duration::from_secs(10)).build()?))
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Diagnosis::fail(
+                "network",
+                e.to_string(),
+                "Fix the proxy or CA certificates in moon's config",
+            )]
+        }
+    };
+    let mut diagnoses = vec![];
+    // through moon's own GitHub client, so it's authenticated and cached like
+    // every other request to it
+    let latest = format!(
+        "{}/repos/{}/releases/latest",
+        network.github_api(),
+        network.repo()
+    );
+    diagnoses.push(
+        match tokio::time::timeout(Duration::from_secs(10), github::check()).await {
+            Ok(Ok(_)) => Diagnosis::pass("network", format!("{} is reachable", latest)),
+            Ok(Err(e)) if e.is::<RateLimited>() => Diagnosis::warn(
+                "network",
+                e.to_string(),
+                "Try again later, or set a GitHub token with moon config --github-token",
+            ),
+            Ok(Err(e)) => Diagnosis::fail(
+                "network",
+                format!("{} failed: {}", latest, e),
+                "Check your connection, proxy, firewall and GitHub token",
+            ),
+            Err(_) => Diagnosis::warn(
+                "network",
+                format!("{} didn't answer within 10s", latest),
+                "It may be slow or waiting out GitHub's rate limit, try again later",
+            ),
+        },
+    );
+    let endpoints = [format!("{}/ref", network.nightly_url())];
+    for endpoint in &endpoints {
+        diagnoses.push(match client.get(endpoint).send().await {
+            Ok(res) if res.status().is_success() => {
+                Diagnosis::pass("network", format!("{} is reachable", endpoint))
+            }
+            Ok(res) => Diagnosis::warn(
+                "network",
+                format!("{} answered {}", endpoint, res.status()),
+                "Check the nightly URL in moon's config",
+            ),
+            Err(e) => Diagnosis::fail(
+                "network",
+                format!("{} is unreachable: {}", endpoint, e),
+                "Check your connection, proxy and firewall settings",
+            ),
+        });
+    }
+    diagnoses
+}
+
+#[inline(always)]
+fn check_toolchain() -> Vec<Diagnosis> {
+    let mut diagnoses = vec![];
+    for (name, cmd) in [("node", "node".to_owned()), ("pnpm", Platform::pnpm())] {
+        diagnoses.push(
+            if Platform::cmd_is_ok(vec![cmd, "--version".to_owned()], None) {
+                Diagnosis::pass(name, "available")
+            } else {
+                Diagnosis::warn(
+                    name,
+                    "not found, the git channel can't build moonlight",
+                    format!("Install {} if you want to use --channel git", name),
+                )
+            },
+        );
+    }
+    diagnoses
+}
+
+#[inline(always)]
+pub async fn doctor(_args: Args) {
+    let mut diagnoses = vec![check_user()];
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            diagnoses.push(Diagnosis::fail(
+                "config",
+                format!("{:?} is invalid: {}", Config::path(), e),
+                "Fix or delete it, moon config shows what it should contain",
+            ));
+            Config::default()
+        }
+    };
+
+    let mut found = false;
+    let mut roots = vec![Config::default_root()];
+    for kind in DiscordKind::ALL {
+        let root = config.root(kind);
+        for install in Platform::installs_by_kind(kind) {
+            found = true;
+            diagnoses.extend(check_install(&install, &root));
+            if !roots.contains(&root) {
+                roots.push(root.clone());
+            }
+        }
+    }
+    if !found {
+        diagnoses.push(Diagnosis::fail(
+            "installs",
+            "no Discord install found",
+            "Install Discord from discord.com, moon looks in the default locations",
+        ));
+    }
+
+    for root in &roots {
+        diagnoses.extend(check_root(root));
+    }
+    diagnoses.extend(check_network(&config.network).await);
+    diagnoses.extend(check_toolchain());
+
+    l::info!("Diagnostics:");
+    for diagnosis in &diagnoses {
+        diagnosis.print();
+    }
+    let warns = diagnoses.iter().filter(|d| d.status == Status::Warn).count();
+    let fails = diagnoses.iter().filter(|d| d.status == Status::Fail).count();
+    l::info!(
+        "{} checks, {} warnings, {} failures",
+        diagnoses.len(),
+        warns,
+        fails
+    );
+}
@@ -1,31 +1,60 @@
-use crate::{cli::args::down::Args, discord::DiscordKind};
+use crate::{
+    cli::args::down::Args,
+    plan::{Action, Plan, Summary},
+};
 
 use log as l;
 
+use super::lock_installs;
+
 #[inline(always)]
 pub async fn down(args: Args) {
-    let kind = DiscordKind::from(args.branch.clone());
-    let plat_installs =crate::Platform::installs_by_kind(kind);
-    let install = match plat_installs.first() {
-        Some(install) => install,
-        None => {
-            l::error!("No Discord install found for branch {:?}", kind);
+    // held from planning until every plan has run
+    let (_locks, installs) = match lock_installs(&args.branch, args.all).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't uninject: {}", e);
             return;
         }
     };
-    l::info!("Killing discord...");
-    loop {
-        match install.kill().await {
-            Ok(_) => continue,
-            Err(_) => {
-                break;
+    if installs.is_empty() {
+        l::error!("No Discord install found for branch {:?}", args.branch);
+        return;
+    }
+
+    let mut summary = Summary::default();
+    let mut plans = vec![];
+    for install in &installs {
+        let mut plan = Plan::default();
+        plan.push(Action::Kill(install));
+        match plan.uninject(install) {
+            Ok(_) => plans.push((install, plan)),
+            Err(e) => {
+                l::error!("Can't uninject Discord {:?}: {}", install.kind, e);
+                summary.record(install, Err(e));
             }
         }
     }
-    l::info!("Uninjecting...");
-    if let Err(e) = install.uninject().await {
-        l::error!("Failed to uninject: {}", e);
+
+    if args.dry_run {
+        for (install, plan) in &plans {
+            l::info!("Discord {:?} at {:?}:", install.kind, install.path);
+            plan.print();
+        }
         return;
-    };
-    l::info!("Done!");
-}
\ No newline at end of file
+    }
+    for (install, plan) in plans {
+        let result = plan.execute().await;
+        if let Err(e) = &result {
+            l::error!("Failed to uninject Discord {:?}: {}", install.kind, e);
+        }
+        summary.record(install, result);
+    }
+
+    if installs.len() > 1 {
+        summary.print();
+    }
+    if summary.ok() {
+        l::info!("Done!");
+    }
+}
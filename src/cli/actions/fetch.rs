@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use crate::{bundle, cli::args::fetch::Args, moonlight::Channel};
+
+use log as l;
+
+#[inline(always)]
+pub async fn fetch(args: Args) {
+    let channel = Channel::from(args.channel);
+    if args.version.is_some() && channel != Channel::Stable {
+        l::error!("Only stable releases can be pinned, not {:?}", channel);
+        return;
+    }
+    let output = PathBuf::from(&args.output);
+    match bundle::fetch(
+        channel,
+        args.version.as_deref(),
+        args.openasar,
+        &args.extensions,
+        &output,
+    )
+    .await
+    {
+        Ok(manifest) => {
+            l::info!(
+                "Bundled moonlight {:?} {} into {:?}",
+                manifest.channel,
+                manifest.version,
+                output
+            );
+            l::info!("Run moon up --bundle {:?} to install it offline", output);
+        }
+        Err(e) => l::error!("Failed to fetch the bundle: {}", e),
+    }
+}
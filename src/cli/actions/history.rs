@@ -0,0 +1,88 @@
+use crate::{
+    cli::args::history::Args,
+    discord::DiscordKind,
+    state::{format_time, State},
+};
+
+use log as l;
+
+#[inline(always)]
+pub async fn history(args: Args) {
+    let kind = args.branch.map(DiscordKind::from);
+    let (state, events) = match State::load().and_then(|state| Ok((state, State::history()?))) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            l::error!("Failed to read moon's state: {}", e);
+            return;
+        }
+    };
+
+    l::info!("Managed installs:");
+    let installs = state
+        .installs
+        .iter()
+        .filter(|m| kind.map(|k| k == m.kind).unwrap_or(true))
+        .collect::<Vec<_>>();
+    if installs.is_empty() {
+        l::info!("  none");
+    }
+    for managed in &installs {
+        l::info!(
+            "  Discord {:?} at {:?}: {:?}, root {:?}, moonlight {} {} (since {})",
+            managed.kind,
+            managed.path,
+            managed.strategy,
+            managed.root,
+            managed
+                .channel
+                .map(|c| format!("{:?}", c))
+                .unwrap_or_else(|| "unknown".to_string()),
+            managed.version.as_deref().unwrap_or("unknown"),
+            format_time(managed.updated)
+        );
+    }
+
+    // updates aren't tied to a branch, but still matter to it if they went
+    // into a root one of its installs loads
+    let roots = installs.iter().map(|m| &m.root).collect::<Vec<_>>();
+    let events = events
+        .iter()
+        .filter(|e| match (kind, e.kind) {
+            (None, _) => true,
+            (Some(k), Some(event_kind)) => k == event_kind,
+            (Some(_), None) => e.root.as_ref().map(|r| roots.contains(&r)).unwrap_or(false),
+        })
+        .collect::<Vec<_>>();
+
+    l::info!("History ({}):", State::history_path().to_string_lossy());
+    if events.is_empty() {
+        l::info!("  nothing yet");
+    }
+    for event in events {
+        let target = match (event.kind, &event.install, &event.root) {
+            (Some(kind), Some(install), _) => format!("Discord {:?} at {:?}", kind, install),
+            (_, _, Some(root)) => format!("{:?}", root),
+            _ => String::new(),
+        };
+        let dist = match (&event.channel, &event.version) {
+            (Some(channel), Some(version)) => format!(", moonlight {:?} {}", channel, version),
+            _ => String::new(),
+        };
+        match &event.error {
+            None => l::info!(
+                "  {} UTC  {:<9} {}{}",
+                format_time(event.time),
+                event.action,
+                target,
+                dist
+            ),
+            Some(error) => l::error!(
+                "  {} UTC  {:<9} {} failed: {}",
+                format_time(event.time),
+                event.action,
+                target,
+                error
+            ),
+        }
+    }
+}
@@ -2,8 +2,66 @@ mod up;
 mod down;
 mod dev;
 mod openasar;
+mod restore;
+mod verify;
+mod doctor;
+mod adopt;
+mod relocate;
+mod config;
+mod history;
+mod fetch;
+mod rollback;
+mod purge;
+mod asar;
 
 pub use up::up;
 pub use down::down;
 pub use dev::dev;
-pub use openasar::openasar;
\ No newline at end of file
+pub use openasar::openasar;
+pub use restore::restore;
+pub use verify::verify;
+pub use doctor::doctor;
+pub use adopt::adopt;
+pub use relocate::relocate;
+pub use config::config;
+pub use history::history;
+pub use fetch::fetch;
+pub use rollback::rollback;
+pub use purge::purge;
+pub use asar::asar;
+
+use std::error::Error;
+
+use crate::discord::{DiscordInstall, DiscordKind};
+use crate::lock::Lock;
+use crate::Platform;
+
+/// Every detected install with `all`, otherwise the first one of `branch`.
+#[inline(always)]
+fn select_installs(branch: &str, all: bool) -> Vec<DiscordInstall> {
+    if all {
+        DiscordKind::ALL
+            .into_iter()
+            .flat_map(Platform::installs_by_kind)
+            .collect()
+    } else {
+        Platform::installs_by_kind(DiscordKind::from(branch.to_string()))
+            .into_iter()
+            .take(1)
+            .collect()
+    }
+}
+
+/// `select_installs`, locked against other moon runs and then detected again
+/// under the locks, so a plan made from them still holds when it runs. The
+/// locks are held until the returned ones are dropped.
+async fn lock_installs(
+    branch: &str,
+    all: bool,
+) -> Result<(Vec<Lock>, Vec<DiscordInstall>), Box<dyn Error>> {
+    let mut locks = vec![];
+    for install in select_installs(branch, all) {
+        locks.push(Lock::acquire(&install.path).await?);
+    }
+    Ok((locks, select_installs(branch, all)))
+}
@@ -1,6 +1,74 @@
-use crate::cli::args::openasar::Args;
+use crate::{
+    cli::args::openasar::Args,
+    discord::{openasar_download, DiscordKind},
+    plan::{Action, Plan},
+};
+
+use log as l;
+
+use super::lock_installs;
 
 #[inline(always)]
-pub async fn openasar(_args: Args) {
+pub async fn openasar(args: Args) {
+    let kind = DiscordKind::from(args.branch.clone());
+    // held from planning until it has run
+    let (_locks, installs) = match lock_installs(&args.branch, false).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't change OpenAsar: {}", e);
+            return;
+        }
+    };
+    let install = match installs.first() {
+        Some(install) => install,
+        None => {
+            l::error!("No Discord install found for branch {:?}", kind);
+            return;
+        }
+    };
+
+    let mut plan = Plan::default();
+    if args.toggle == "up" {
+        // downloaded before Discord is killed, so a failed download leaves it running
+        let openasar = openasar_download();
+        plan.push(Action::DownloadOpenAsar(openasar.clone()));
+        plan.push(Action::Kill(install));
+        plan.openasar_up(install, &openasar);
+    } else {
+        plan.push(Action::Kill(install));
+        if let Err(e) = plan.openasar_down(install) {
+            l::error!("Can't remove OpenAsar: {}", e);
+            return;
+        }
+    }
+
+    if args.dry_run {
+        plan.print();
+        return;
+    }
+    if let Err(e) = plan.execute().await {
+        l::error!(
+            "Failed to {} OpenAsar: {}",
+            if args.toggle == "up" {
+                "install"
+            } else {
+                "remove"
+            },
+            e
+        );
+        return;
+    }
 
-}
\ No newline at end of file
+    // look the install up again so is_openasar reflects what's there now
+    let is_openasar = crate::Platform::installs_by_kind(kind)
+        .into_iter()
+        .find(|i| i.path == install.path)
+        .map(|i| i.is_openasar)
+        .unwrap_or_default();
+    l::info!(
+        "OpenAsar is {}installed in Discord {:?}",
+        if is_openasar { "" } else { "not " },
+        kind
+    );
+    l::info!("Done!");
+}
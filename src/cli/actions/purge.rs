@@ -0,0 +1,201 @@
+use std::{io::Write, path::PathBuf};
+
+use crate::{
+    cli::args::purge::Args,
+    config::Config,
+    discord::{DiscordInstall, DiscordKind},
+    lock::Lock,
+    moonlight::versions::is_moon_root,
+    plan::{Action, Plan},
+    platform::Platform,
+    state::State,
+};
+
+use log as l;
+
+use super::lock_installs;
+
+/// The installs to uninject: the ones moon recorded as managed, plus any
+/// the scan finds still loading moonlight.
+#[inline(always)]
+fn managed_installs(state: &State) -> Vec<DiscordInstall> {
+    DiscordKind::ALL
+        .into_iter()
+        .flat_map(Platform::installs_by_kind)
+        .filter(|install| {
+            install.injector().is_some() || state.installs.iter().any(|m| m.path == install.path)
+        })
+        .collect()
+}
+
+/// Every moonlight root moon knows of, whether from config, state or the
+/// installs themselves. Only absolute paths moon itself set up count, so a
+/// root misconfigured as e.g. the home folder, or a project that happens to
+/// have a dist/, is never deleted.
+#[inline(always)]
+fn roots(config: &Config, state: &State, installs: &[DiscordInstall]) -> Vec<PathBuf> {
+    let mut roots = vec![Config::default_root()];
+    roots.extend(DiscordKind::ALL.into_iter().map(|kind| config.root(kind)));
+    roots.extend(state.installs.iter().map(|m| m.root.clone()));
+    roots.extend(
+        installs
+            .iter()
+            .filter_map(|install| install.injector()?.moonlight_root()),
+    );
+    roots.sort();
+    roots.dedup();
+    let mut unique: Vec<PathBuf> = vec![];
+    for root in roots {
+        if !root.exists() {
+            continue;
+        }
+        if !root.is_absolute() || !is_moon_root(&root) {
+            l::info!("Leaving {:?} alone, it isn't a moonlight root moon set up", root);
+            continue;
+        }
+        // a root inside another root goes along with it
+        if !unique.iter().any(|r| root.starts_with(r)) {
+            unique.retain(|r| !r.starts_with(&root));
+            unique.push(root);
+        }
+    }
+    unique
+}
+
+/// The Flatpak override `moon doctor` suggests for the sandbox to see a root.
+#[cfg(target_os = "linux")]
+#[inline(always)]
+fn flatpak_override(roots: &[PathBuf]) -> Option<(PathBuf, String, Vec<String>)> {
+    use crate::platform::env;
+    let home = env("HOME", "~/");
+    let file = PathBuf::from(env("XDG_DATA_HOME", &format!("{}/.local/share", home)))
+        .join("flatpak/overrides/com.discordapp.Discord");
+    let contents = std::fs::read_to_string(&file).ok()?;
+    let mut removed = vec![];
+    let lines = contents
+        .lines()
+        .map(|line| match line.strip_prefix("filesystems=") {
+            Some(list) => {
+                let kept = list
+                    .split(';')
+                    .filter(|fs| {
+                        let path = fs.split(':').next().unwrap_or_default().replace('~', &home);
+                        let ours = !path.is_empty()
+                            && roots.iter().any(|r| r == std::path::Path::new(&path));
+                        if ours {
+                            removed.push(fs.to_string());
+                        }
+                        !ours
+                    })
+                    .collect::<Vec<_>>();
+                format!("filesystems={}", kept.join(";"))
+            }
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if removed.is_empty() {
+        return None;
+    }
+    Some((file, lines.join("\n") + "\n", removed))
+}
+
+#[inline(always)]
+fn confirm() -> bool {
+    print!("This can't be undone, continue? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[inline(always)]
+pub async fn purge(args: Args) {
+    let config = Config::load().unwrap_or_else(|e| {
+        l::warn!("Ignoring unreadable {:?}: {}", Config::path(), e);
+        Config::default()
+    });
+    let state = State::load().unwrap_or_else(|e| {
+        l::warn!("Ignoring unreadable {:?}: {}", State::path(), e);
+        State::default()
+    });
+    // every install and root is held from planning until it has run
+    let mut locks = match lock_installs("stable", true).await {
+        Ok((locks, _)) => locks,
+        Err(e) => {
+            l::error!("Can't purge: {}", e);
+            return;
+        }
+    };
+    let installs = managed_installs(&state);
+    let roots = roots(&config, &state, &installs);
+    for root in &roots {
+        match Lock::acquire(root).await {
+            Ok(lock) => locks.push(lock),
+            Err(e) => {
+                l::error!("Can't lock {:?}: {}", root, e);
+                return;
+            }
+        }
+    }
+
+    let mut plan = Plan::default();
+    for install in installs.iter().filter(|install| install.injected) {
+        plan.push(Action::Kill(install));
+        if let Err(e) = plan.uninject(install) {
+            l::error!("Can't uninject Discord {:?}: {}", install.kind, e);
+            return;
+        }
+    }
+    for root in &roots {
+        plan.push(Action::Delete(root.clone()));
+    }
+    // backups, journals, config, history and the locks all live here, so it
+    // goes last, once the locks are let go
+    let data_dir = Some(PathBuf::from(Platform::data_dir())).filter(|dir| dir.exists());
+
+    #[cfg(target_os = "linux")]
+    let flatpak = flatpak_override(&roots);
+    #[cfg(not(target_os = "linux"))]
+    let flatpak: Option<(PathBuf, String, Vec<String>)> = None;
+
+    plan.print();
+    if let Some(data_dir) = &data_dir {
+        l::info!("  and delete moon's data at {:?}", data_dir);
+    }
+    if let Some((file, _, removed)) = &flatpak {
+        l::info!(
+            "  and drop {} from the Flatpak override in {:?}",
+            removed.join(", "),
+            file
+        );
+    }
+    if plan.actions.is_empty() && data_dir.is_none() && flatpak.is_none() {
+        return;
+    }
+    if !args.yes && !confirm() {
+        l::info!("Nothing was removed");
+        return;
+    }
+
+    if let Some((file, contents, removed)) = flatpak {
+        match std::fs::write(&file, contents) {
+            Ok(_) => l::info!("Removed {} from {:?}", removed.join(", "), file),
+            Err(e) => l::error!("Failed to update {:?}: {}", file, e),
+        }
+    }
+    if let Err(e) = plan.execute().await {
+        l::error!("Failed to purge: {}", e);
+        l::error!("Run moon purge again to remove what's left");
+        return;
+    }
+    drop(locks);
+    if let Some(data_dir) = data_dir {
+        if let Err(e) = std::fs::remove_dir_all(&data_dir) {
+            l::error!("Failed to remove {:?}: {}", data_dir, e);
+            l::error!("Run moon purge again to remove what's left");
+            return;
+        }
+        l::info!("Removed {:?}", data_dir);
+    }
+    l::info!("moonlight is gone from this machine");
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::args::relocate::Args,
+    config::Config,
+    discord::DiscordKind,
+    lock::Lock,
+    plan::{Action, Plan},
+    platform::Platform,
+};
+
+use log as l;
+
+use super::lock_installs;
+
+#[inline(always)]
+pub async fn relocate(args: Args) {
+    let from = args
+        .from
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_root);
+    let from = fs::canonicalize(&from).unwrap_or(from);
+    let to = match std::path::absolute(PathBuf::from(&args.new_root)) {
+        Ok(to) => to,
+        Err(e) => {
+            l::error!("Invalid new root {}: {}", args.new_root, e);
+            return;
+        }
+    };
+    if from == to {
+        l::error!("{:?} is already the moonlight root", from);
+        return;
+    }
+
+    let config = Config::load().unwrap_or_else(|e| {
+        l::warn!("Ignoring unreadable {:?}: {}", Config::path(), e);
+        Config::default()
+    });
+    // branches with their own channel but no root keep theirs beside the
+    // default root, as `<root>-<branch>`, so they follow it there
+    let mut moves = vec![(from.clone(), to.clone())];
+    let default_root = Config::default_root();
+    if from == fs::canonicalize(&default_root).unwrap_or(default_root) {
+        for kind in DiscordKind::ALL {
+            if config.derives_root(kind) {
+                moves.push((
+                    Config::derived_root(&from, kind),
+                    Config::derived_root(&to, kind),
+                ));
+            }
+        }
+    }
+
+    // every install and root is held from planning until it has run
+    let mut locks = vec![];
+    for root in moves.iter().flat_map(|(from, to)| [from, to]) {
+        match Lock::acquire(root).await {
+            Ok(lock) => locks.push(lock),
+            Err(e) => {
+                l::error!("Can't lock {:?}: {}", root, e);
+                return;
+            }
+        }
+    }
+    let installs = match lock_installs("stable", true).await {
+        Ok((install_locks, installs)) => {
+            locks.extend(install_locks);
+            installs
+        }
+        Err(e) => {
+            l::error!("Can't relocate: {}", e);
+            return;
+        }
+    };
+    // installs loading a root that moves, or a root configured inside one,
+    // are repointed to where it ends up
+    let affected = installs
+        .iter()
+        .filter_map(|install| {
+            let root = install.injector()?.moonlight_root()?;
+            let root = fs::canonicalize(&root).unwrap_or(root);
+            moves
+                .iter()
+                .find_map(|(from, to)| Some((install, to.join(root.strip_prefix(from).ok()?))))
+        })
+        .collect::<Vec<_>>();
+
+    let mut plan = Plan::default();
+    for (install, _) in &affected {
+        plan.push(Action::Kill(install));
+    }
+    if from.exists() {
+        if to.exists() && fs::read_dir(&to).map(|mut d| d.next().is_some()).unwrap_or(true) {
+            l::error!("{:?} already exists and isn't empty", to);
+            return;
+        }
+        plan.push(Action::Move {
+            from: from.clone(),
+            to: to.clone(),
+        });
+    } else if to.exists() {
+        // an earlier relocate got as far as moving, just repoint what's left
+        l::warn!("{:?} is already gone, only repointing installs", from);
+    } else {
+        l::error!("Neither {:?} nor {:?} exist", from, to);
+        return;
+    }
+    for (from, to) in moves.iter().skip(1).filter(|(from, _)| from.exists()) {
+        if to.exists() {
+            l::error!("{:?} already exists", to);
+            return;
+        }
+        plan.push(Action::Move {
+            from: from.clone(),
+            to: to.clone(),
+        });
+    }
+    for (install, root) in &affected {
+        if let Err(e) = plan.retarget(install, root) {
+            l::error!("Can't repoint Discord {:?}: {}", install.kind, e);
+            return;
+        }
+    }
+
+    if args.dry_run {
+        plan.print();
+        return;
+    }
+    if let Err(e) = plan.execute().await {
+        l::error!("Failed to relocate: {}", e);
+        l::error!("Run the same moon relocate again to finish repointing installs");
+        return;
+    }
+    // branch roots configured inside the old root moved with it, derived
+    // ones follow MOONLIGHT_ROOT by themselves
+    match Config::load() {
+        Ok(mut config) => {
+            let mut changed = false;
+            for branch in config.branches.values_mut() {
+                if let Some(rest) = branch.root.as_ref().and_then(|r| r.strip_prefix(&from).ok()) {
+                    branch.root = Some(to.join(rest));
+                    changed = true;
+                }
+            }
+            if changed {
+                if let Err(e) = config.save() {
+                    l::error!("Failed to update {:?}: {}", Config::path(), e);
+                }
+            }
+        }
+        Err(e) => l::warn!("Failed to read {:?}, not updating it: {}", Config::path(), e),
+    }
+    l::info!(
+        "Moved moonlight to {:?}, {} installs repointed",
+        to,
+        affected.len()
+    );
+    if to != Path::new(&Platform::conf_dir()) {
+        l::warn!(
+            "Remember to set MOONLIGHT_ROOT={} for future moon runs",
+            to.to_string_lossy()
+        );
+    }
+}
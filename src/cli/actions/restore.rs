@@ -0,0 +1,33 @@
+use crate::{cli::args::restore::Args, discord::DiscordKind};
+
+use log as l;
+
+use super::lock_installs;
+
+#[inline(always)]
+pub async fn restore(args: Args) {
+    let kind = DiscordKind::from(args.branch.clone());
+    // held from planning until it has run
+    let (_locks, installs) = match lock_installs(&args.branch, false).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't restore: {}", e);
+            return;
+        }
+    };
+    let install = match installs.first() {
+        Some(install) => install,
+        None => {
+            l::error!("No Discord install found for branch {:?}", kind);
+            return;
+        }
+    };
+    l::info!("Killing discord...");
+    while install.kill().await.is_ok() {}
+    l::info!("Restoring...");
+    if let Err(e) = install.restore().await {
+        l::error!("Failed to restore: {}", e);
+        return;
+    };
+    l::info!("Done!");
+}
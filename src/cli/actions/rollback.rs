@@ -0,0 +1,110 @@
+use crate::{
+    cli::args::rollback::Args,
+    config::Config,
+    discord::DiscordKind,
+    lock::Lock,
+    moonlight::{
+        versions::{
+            activate, active_version, find_version, list_versions, previous_version, versions_dir,
+        },
+        DIST_FILES,
+    },
+    state::{format_time, State},
+};
+use std::time::UNIX_EPOCH;
+
+use log as l;
+
+#[inline(always)]
+pub async fn rollback(args: Args) {
+    let kind = DiscordKind::from(args.branch);
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            l::error!("Failed to read {:?}: {}", Config::path(), e);
+            return;
+        }
+    };
+    let root = config.root(kind);
+    let active = active_version(&root);
+    let previous = previous_version(&root);
+
+    if args.list {
+        let versions = list_versions(&root);
+        l::info!("Versions kept in {:?}:", root);
+        if versions.is_empty() {
+            l::info!("  none, run moon up first");
+        }
+        for version in versions {
+            let tag = if Some(&version.id) == active.as_ref() {
+                " (active)"
+            } else if Some(&version.id) == previous.as_ref() {
+                " (previous)"
+            } else {
+                ""
+            };
+            let installed = version
+                .installed
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|t| format!(", downloaded {} UTC", format_time(t.as_secs())))
+                .unwrap_or_default();
+            l::info!("  {}{}{}", version.id, tag, installed);
+        }
+        return;
+    }
+
+    // the root can't change under a download or another rollback
+    let _lock = match Lock::acquire(&root).await {
+        Ok(lock) => lock,
+        Err(e) => {
+            l::error!("{}", e);
+            return;
+        }
+    };
+    let target = match &args.version {
+        Some(name) => match find_version(&root, name) {
+            Some(version) => version.id,
+            None => {
+                l::error!(
+                    "{} isn't kept in {:?}, see moon rollback --list",
+                    name,
+                    root
+                );
+                return;
+            }
+        },
+        None => match previous.filter(|id| find_version(&root, id).is_some()) {
+            Some(id) => id,
+            None => {
+                l::error!("No previous version is kept in {:?}", root);
+                return;
+            }
+        },
+    };
+    if Some(&target) == active.as_ref() {
+        l::info!("moonlight {} is already active", target);
+        return;
+    }
+    let dir = versions_dir(&root).join(&target);
+    if let Some(file) = DIST_FILES.iter().find(|file| !dir.join(file).is_file()) {
+        l::error!("{} is missing from {:?}, can't roll back to it", file, dir);
+        return;
+    }
+
+    let result = activate(&root, &target);
+    State::record_rollback(&root, &result).await;
+    if let Err(e) = result {
+        l::error!("Failed to roll back to {}: {}", target, e);
+        return;
+    }
+    l::info!(
+        "Rolled back to moonlight {}, restart Discord to load it",
+        target
+    );
+    if config.version(kind).is_none() {
+        l::info!(
+            "moon up will update it again, pin a version with moon up -b {} --version to stay on it",
+            kind.branch_name()
+        );
+    }
+}
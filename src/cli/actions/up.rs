@@ -1,74 +1,249 @@
 use std::path::PathBuf;
 
-use crate::{cli::args::up::Args, discord::DiscordKind, moonlight::{Channel, init_moonlight}, platform::{Platform, env}};
-
+use crate::{
+    bundle::Bundle,
+    cli::args::up::Args,
+    config::Config,
+    discord::ForeignPolicy,
+    moonlight::Channel,
+    plan::{Action, Plan, Summary},
+};
 
 use log as l;
-use tokio::{sync::mpsc, spawn};
+
+use super::lock_installs;
+
 #[inline(always)]
 pub async fn up(args: Args) {
-    let kind = DiscordKind::from(args.branch.clone());
-    let plat_installs =crate::Platform::installs_by_kind(kind);
-    let install = match plat_installs.first() {
-        Some(install) => install,
-        None => {
-            l::error!("No Discord install found for branch {:?}", kind);
+    // held from planning until every plan has run
+    let (_locks, installs) = match lock_installs(&args.branch, args.all).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't inject: {}", e);
+            return;
+        }
+    };
+    if installs.is_empty() {
+        l::error!("No Discord install found for branch {:?}", args.branch);
+        return;
+    }
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            l::error!("Failed to read {:?}: {}", Config::path(), e);
+            return;
+        }
+    };
+    let policy = match args.foreign.parse::<ForeignPolicy>() {
+        Ok(policy) => policy,
+        Err(e) => {
+            l::error!("{}", e);
+            return;
+        }
+    };
+    let from = match args
+        .from
+        .as_ref()
+        .map(|from| std::path::absolute(PathBuf::from(from)))
+    {
+        Some(Ok(from)) if from.exists() => Some(from),
+        Some(Ok(from)) => {
+            l::error!("{:?} doesn't exist", from);
+            return;
+        }
+        Some(Err(e)) => {
+            l::error!("Invalid --from path: {}", e);
             return;
         }
+        None => None,
     };
-    let channel = Channel::from(args.channel.clone());
-    let root = env("MOONLIGHT_ROOT", &Platform::conf_dir());
+    let bundle = match args
+        .bundle
+        .as_ref()
+        .map(|bundle| Bundle::open(&PathBuf::from(bundle)))
+    {
+        Some(Ok(bundle)) => Some(bundle),
+        Some(Err(e)) => {
+            l::error!("Can't use the bundle: {}", e);
+            return;
+        }
+        None => None,
+    };
+
+    // --channel and --version are remembered, so later runs stay on them. A
+    // branch taken off the shared channel gets its own root that way, rather
+    // than another channel landing in the root the other branches load
+    let mut remember = args.version.is_some();
+    if let Some(channel) = args.channel.clone().map(Channel::from) {
+        for install in &installs {
+            if config.channel(install.kind).unwrap_or(Channel::Stable) != channel {
+                let mut branch = config.branch(install.kind);
+                branch.channel = Some(channel);
+                config.set_branch(install.kind, branch);
+                remember = true;
+            }
+        }
+    }
+    if let Some(version) = &args.version {
+        for install in &installs {
+            let mut branch = config.branch(install.kind);
+            branch.version = (version != "latest").then(|| version.clone());
+            config.set_branch(install.kind, branch);
+        }
+    }
+    for install in &installs {
+        let channel = config.channel(install.kind).unwrap_or(Channel::Stable);
+        if let Some(version) = config.version(install.kind) {
+            if channel != Channel::Stable {
+                l::error!(
+                    "Discord {:?} would be on {:?} pinned to {}, only stable releases can be pinned, supply --version latest to unpin",
+                    install.kind,
+                    channel,
+                    version
+                );
+                return;
+            }
+        }
+    }
 
-    let (tx, mut rx) = mpsc::channel::<bool>(1);
+    // every install gets the dist for its own branch's channel and root
+    let mut roots: Vec<(PathBuf, Channel, Option<String>)> = vec![];
+    let mut targets = vec![];
+    let mut summary = Summary::default();
     l::info!("Install settings:");
-    l::info!("  Using root directory {}", root);
-    l::info!("  Using Discord install at {:?}", install.path);
-    l::info!("  Using Moonlight channel {:?}", channel);
-    l::info!("Making sure moonlight is up to date...");
-    let root = PathBuf::from(root);
-    let rt = root.clone();
-    let ct = channel.clone();
-    let t = spawn((async move || {
-        let mut needs_revert = false;
-        if let Err(e) = init_moonlight(rt, Some(ct), None).await {
-            l::error!("Failed to update moonlight: {}", e);
-            needs_revert = true;
-        };
-        let _ = tx.send(needs_revert).await;
-        
-    })());
-    l::info!("Killing discord...");
-    loop {
-        match install.kill().await {
-            Ok(_) => continue,
-            Err(_) => {
-                break;
+    for install in &installs {
+        let channel = config.channel(install.kind).unwrap_or(Channel::Stable);
+        let version = config.version(install.kind);
+        let root = config.root(install.kind);
+        l::info!(
+            "  Discord {:?} at {:?}: channel {:?}{}, root {:?}",
+            install.kind,
+            install.path,
+            channel,
+            version
+                .as_ref()
+                .map(|v| format!(" pinned to {}", v))
+                .unwrap_or_default(),
+            root
+        );
+        match roots.iter().find(|(r, _, _)| *r == root) {
+            Some((_, other, other_version)) if *other != channel || *other_version != version => {
+                let e = format!(
+                    "{:?} is also used with channel {:?} {}, see moon config",
+                    root,
+                    other,
+                    other_version.as_deref().unwrap_or("latest")
+                );
+                l::error!("Can't inject Discord {:?}: {}", install.kind, e);
+                summary.record(install, Err(e.into()));
+                continue;
             }
+            Some(_) => {}
+            None => roots.push((root.clone(), channel, version)),
         }
+        targets.push((install, root));
     }
 
-    l::info!("Injecting...");
-    if let Err(e) = install.inject(&root).await {
-        l::error!("Failed to inject: {}", e);
+    // moonlight is brought up to date once per root, before any Discord is
+    // touched, so a failed download never leaves an install injected with a
+    // broken dist
+    let mut download = Plan::default();
+    for (root, channel, version) in roots {
+        // a dist from disk only goes into a root whose branch is configured
+        // for its channel, unless --channel just switched it
+        let configured = targets
+            .iter()
+            .filter(|(_, r)| *r == root)
+            .find_map(|(install, _)| config.channel(install.kind))
+            .filter(|_| args.channel.is_none());
+        download.push(match (&from, &bundle) {
+            (Some(from), _) => Action::InstallLocal {
+                from: from.clone(),
+                root,
+                recorded: None,
+                channel: configured,
+            },
+            (_, Some(bundle)) => Action::InstallLocal {
+                from: bundle.dist(),
+                root,
+                recorded: Some((bundle.manifest.channel, bundle.manifest.version.clone())),
+                channel: configured,
+            },
+            (None, None) => Action::Download {
+                channel,
+                root,
+                version,
+            },
+        });
+    }
+
+    let mut plans = vec![];
+    for (install, root) in targets {
+        let mut plan = Plan::default();
+        plan.push(Action::Kill(install));
+        // OpenAsar goes in first, injecting moves whatever asar is in place
+        if let Some(openasar) = bundle.as_ref().and_then(|b| b.openasar()) {
+            plan.openasar_up(install, &openasar);
+        }
+        match plan.inject(install, &root, policy) {
+            Ok(_) => {
+                if let Some(bundle) = &bundle {
+                    plan.extensions(
+                        install,
+                        bundle
+                            .manifest
+                            .extensions
+                            .iter()
+                            .map(|e| (e.id.clone(), bundle.extension(&e.id)))
+                            .collect(),
+                    );
+                }
+                plans.push((install, plan))
+            }
+            Err(e) => {
+                l::error!("Can't inject Discord {:?}: {}", install.kind, e);
+                summary.record(install, Err(e));
+            }
+        }
+    }
+
+    if args.dry_run {
+        download.print();
+        for (install, plan) in &plans {
+            l::info!("Discord {:?} at {:?}:", install.kind, install.path);
+            plan.print();
+        }
         return;
-    };
-    let _ = t.await;
-    match rx.recv().await {
-        Some(true) => {
-            l::error!("Failed to update moonlight, uninjecting...");
-            if let Err(e) = install.uninject().await {
-                l::error!("Failed to uninject: {}", e);
-                l::error!("Please manually uninject moonlight from Discord");
-                return;
-            };
-            return;
-        },
-        Some(false) => {
-            
-        },
-        _ => {
-            l::warn!("no message in mpsc channel, assuming success")
+    }
+    if plans.is_empty() {
+        if installs.len() > 1 {
+            summary.print();
         }
+        return;
+    }
+    if let Err(e) = download.execute().await {
+        l::error!("Failed to download moonlight: {}", e);
+        return;
+    }
+    // only once the download worked, a pin to a version that doesn't exist
+    // would fail every later run
+    if remember {
+        if let Err(e) = config.save() {
+            l::error!("Failed to write {:?}: {}", Config::path(), e);
+        }
+    }
+    for (install, plan) in plans {
+        let result = plan.execute().await;
+        if let Err(e) = &result {
+            l::error!("Failed to inject Discord {:?}: {}", install.kind, e);
+        }
+        summary.record(install, result);
+    }
+
+    if installs.len() > 1 {
+        summary.print();
+    }
+    if summary.ok() {
+        l::info!("Done!");
     }
-    l::info!("Done!");
-}
\ No newline at end of file
+}
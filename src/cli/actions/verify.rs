@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cli::args::verify::Args,
+    config::Config,
+    discord::{Check, DiscordInstall, Repair},
+    moonlight::{download, recorded_channel, Channel},
+    plan::Summary,
+    state::State,
+    platform::Platform,
+};
+
+use log as l;
+
+use super::lock_installs;
+
+#[inline(always)]
+fn report(checks: &[Check]) -> bool {
+    let mut ok = true;
+    for check in checks {
+        match &check.result {
+            Ok(detail) => l::info!("  [ok]   {}: {}", check.name, detail),
+            Err(detail) => {
+                ok = false;
+                l::error!("  [fail] {}: {}", check.name, detail)
+            }
+        }
+    }
+    ok
+}
+
+/// The channel to redownload the dist under `root` from. A dist installed
+/// from disk can only be replaced by the channel configured for `install`,
+/// without one it's `None`, as there's nothing to fetch it from.
+#[inline(always)]
+fn dist_channel(install: &DiscordInstall, root: &Path, config: &Config) -> Option<Channel> {
+    let configured = config
+        .channel(install.kind)
+        .filter(|c| *c != Channel::Local);
+    match recorded_channel(root) {
+        Some(Channel::Local) => configured,
+        recorded => Some(recorded.or(configured).unwrap_or(Channel::Stable)),
+    }
+}
+
+#[inline(always)]
+async fn repair(
+    install: &DiscordInstall,
+    repair: Repair,
+    root: &PathBuf,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match repair {
+        Repair::Asar => install.repair_asar().await,
+        Repair::InjectionFiles => install.modify_moonlight_root(root).await,
+        Repair::Dist => {
+            let channel = dist_channel(install, root, config)
+                .ok_or("a dist installed from disk can't be downloaded again")?;
+            let version = config
+                .version(install.kind)
+                .filter(|_| channel == Channel::Stable);
+            l::info!("Redownloading moonlight (channel: {:?})", channel);
+            let result = download(channel, root.clone(), None, version, false)
+                .await
+                .map(|_| ());
+            State::record_update(root, &result).await;
+            result
+        }
+    }
+}
+
+/// Verifies one install, repairing it if asked. Dist roots already
+/// redownloaded for an earlier install in `fetched` aren't fetched again.
+#[inline(always)]
+async fn verify_install(
+    install: &DiscordInstall,
+    config: &Config,
+    do_repair: bool,
+    fetched: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    l::info!("Verifying Discord {:?} at {:?}", install.kind, install.path);
+    let fallback_root = config.root(install.kind);
+    let (checks, root) = install.verify(&fallback_root);
+    if report(&checks) {
+        l::info!("Everything looks good!");
+        return Ok(());
+    }
+    if !install.is_injected() {
+        return Err("not injected".into());
+    }
+    if !do_repair {
+        l::warn!("Run again with --repair to fix what can be fixed");
+        return Err("some checks failed".into());
+    }
+
+    let mut repairs = checks
+        .iter()
+        .filter(|c| c.result.is_err())
+        .filter_map(|c| c.repair)
+        .collect::<Vec<_>>();
+    repairs.sort();
+    repairs.dedup();
+
+    l::info!("Killing discord...");
+    while install.kill().await.is_ok() {}
+    for r in repairs {
+        if r == Repair::Dist && fetched.contains(&root) {
+            l::info!("Dist at {:?} was already redownloaded", root);
+            continue;
+        }
+        if r == Repair::Dist && recorded_channel(&root) == Some(Channel::Local) {
+            match dist_channel(install, &root, config) {
+                Some(channel) => l::warn!(
+                    "Dist at {:?} was installed from disk, replacing it with the configured {:?} channel's",
+                    root,
+                    channel
+                ),
+                None => {
+                    l::warn!(
+                        "Dist at {:?} was installed from disk and can't be repaired, reinstall it with moon up --from",
+                        root
+                    );
+                    continue;
+                }
+            }
+        }
+        l::info!("Repairing {:?}...", r);
+        match repair(install, r, &root, config).await {
+            Ok(_) if r == Repair::Dist => fetched.push(root.clone()),
+            Ok(_) => {}
+            Err(e) => l::error!("Failed to repair {:?}: {}", r, e),
+        }
+    }
+
+    // the install's state changed under us, look it up again
+    let install = Platform::installs_by_kind(install.kind)
+        .into_iter()
+        .find(|i| i.path == install.path)
+        .ok_or("install disappeared while repairing")?;
+    l::info!("Verifying again...");
+    if report(&install.verify(&fallback_root).0) {
+        Ok(())
+    } else {
+        l::error!("Some checks still fail, see above");
+        Err("some checks still fail after repairing".into())
+    }
+}
+
+#[inline(always)]
+pub async fn verify(args: Args) {
+    // held from planning any repair until it has run
+    let (_locks, installs) = match lock_installs(&args.branch, args.all).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            l::error!("Can't verify: {}", e);
+            return;
+        }
+    };
+    if installs.is_empty() {
+        l::error!("No Discord install found for branch {:?}", args.branch);
+        return;
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            l::error!("Failed to read {:?}: {}", Config::path(), e);
+            return;
+        }
+    };
+
+    let mut summary = Summary::default();
+    let mut fetched = vec![];
+    for install in &installs {
+        // --all only means every injected install, never injecting the rest
+        if args.all && !install.is_injected() {
+            summary.skip(install, "not injected");
+            continue;
+        }
+        let result = verify_install(install, &config, args.repair, &mut fetched).await;
+        summary.record(install, result);
+    }
+
+    if installs.len() > 1 {
+        summary.print();
+    }
+    if args.repair && summary.ok() {
+        l::info!("Done!");
+    }
+}
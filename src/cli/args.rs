@@ -6,6 +6,9 @@ use super::Subcommand;
 pub struct Args {
     #[command(subcommand)]
     pub subcommand: Subcommand,
+    /// Fail instead of waiting when another moon run is using the same root or install
+    #[arg(long, global = true)]
+    pub no_wait: bool,
 }
 
 pub(super) mod up {
@@ -13,10 +16,29 @@ pub(super) mod up {
 
     #[derive(Debug, Parser)]
     pub struct Args {
-        #[arg(short, long, default_value = "stable", value_parser(["stable", "nightly", "git"]))]
-        pub channel: String,
+        /// Defaults to the branch's configured channel, or stable
+        #[arg(short, long, value_parser(["stable", "nightly", "git"]))]
+        pub channel: Option<String>,
+        /// Pin a stable release by tag or semver range (e.g. "v1.2.0", "~1.2"), "latest" unpins
+        #[arg(long, value_name = "VERSION")]
+        pub version: Option<String>,
+        /// Install from a dist.tar.gz or unpacked dist on disk instead of downloading
+        #[arg(long, value_name = "PATH", conflicts_with = "version")]
+        pub from: Option<String>,
+        /// Install everything from a bundle made by moon fetch, without downloading
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["version", "from"])]
+        pub bundle: Option<String>,
         #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
         pub branch: String,
+        /// Run on every detected install of every branch
+        #[arg(long, conflicts_with = "branch")]
+        pub all: bool,
+        /// What to do if another client mod is already installed
+        #[arg(long, default_value = "refuse", value_parser(["refuse", "uninstall", "chain"]))]
+        pub foreign: String,
+        /// Print what would be done without doing it
+        #[arg(long)]
+        pub dry_run: bool,
     }
 }
 
@@ -27,6 +49,12 @@ pub(super) mod down {
     pub struct Args {
         #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
         pub branch: String,
+        /// Run on every detected install of every branch
+        #[arg(long, conflicts_with = "branch")]
+        pub all: bool,
+        /// Print what would be done without doing it
+        #[arg(long)]
+        pub dry_run: bool,
     }
 }
 
@@ -52,5 +80,192 @@ pub(super) mod openasar {
 
         #[arg(index = 1, value_parser(["up","down"]), value_name = "TOGGLE")]
         pub toggle: String,
+        /// Print what would be done without doing it
+        #[arg(long)]
+        pub dry_run: bool,
+    }
+}
+
+pub(super) mod restore {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: String,
+    }
+}
+
+pub(super) mod verify {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: String,
+        /// Run on every detected install of every branch
+        #[arg(long, conflicts_with = "branch")]
+        pub all: bool,
+        /// Fix whatever failed, where that can be done safely
+        #[arg(long)]
+        pub repair: bool,
+    }
+}
+
+pub(super) mod doctor {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {}
+}
+
+pub(super) mod adopt {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: String,
+        /// Point the adopted install at this moonlight root instead of the one it uses now
+        #[arg(long, value_name = "ROOT")]
+        pub root: Option<String>,
+    }
+}
+
+pub(super) mod relocate {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        #[arg(index = 1, value_name = "NEW_ROOT")]
+        pub new_root: String,
+        /// The root to move, defaults to MOONLIGHT_ROOT or moon's config directory
+        #[arg(long, value_name = "OLD_ROOT")]
+        pub from: Option<String>,
+        /// Print what would be done without doing it
+        #[arg(long)]
+        pub dry_run: bool,
+    }
+}
+
+pub(super) mod config {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        /// The branch to configure, shows every branch's settings if left out
+        #[arg(short, long, value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: Option<String>,
+        /// The moonlight channel this branch is injected with
+        #[arg(short, long, requires = "branch", value_parser(["stable", "nightly", "git"]))]
+        pub channel: Option<String>,
+        /// The moonlight root this branch is injected with
+        #[arg(long, requires = "branch", value_name = "ROOT")]
+        pub root: Option<String>,
+        /// Forget this branch's settings, going back to the shared defaults
+        #[arg(long, requires = "branch", conflicts_with_all = ["channel", "root"])]
+        pub reset: bool,
+        /// How many old moonlight versions each root keeps for moon rollback
+        #[arg(long, value_name = "COUNT")]
+        pub keep_versions: Option<usize>,
+        /// The GitHub owner/repo stable releases come from, "" for the default
+        #[arg(long, value_name = "OWNER/REPO")]
+        pub repo: Option<String>,
+        /// The GitHub API base URL, "" for the default
+        #[arg(long, value_name = "URL")]
+        pub github_api: Option<String>,
+        /// The base URL serving nightly's ref and dist.tar.gz, "" for the default
+        #[arg(long, value_name = "URL")]
+        pub nightly_url: Option<String>,
+        /// The URL the git channel clones, "" for the default
+        #[arg(long, value_name = "URL")]
+        pub git_url: Option<String>,
+        /// The proxy every request goes through, "" for none
+        #[arg(long, value_name = "URL")]
+        pub proxy: Option<String>,
+        /// A PEM CA certificate to trust, repeat for more, "" for none
+        #[arg(long = "ca-cert", value_name = "PATH")]
+        pub ca_certs: Option<Vec<String>>,
+        /// A GitHub token for a higher API rate limit, "" for none
+        #[arg(long, value_name = "TOKEN")]
+        pub github_token: Option<String>,
+    }
+}
+
+pub(super) mod history {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        /// Only show what happened to this branch
+        #[arg(short, long, value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: Option<String>,
+    }
+}
+
+pub(super) mod fetch {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        /// The moonlight channel to bundle
+        #[arg(short, long, default_value = "stable", value_parser(["stable", "nightly"]))]
+        pub channel: String,
+        /// Bundle a stable release by tag or semver range instead of the latest
+        #[arg(long, value_name = "VERSION")]
+        pub version: Option<String>,
+        /// Where to write the bundle
+        #[arg(short, long, value_name = "PATH")]
+        pub output: String,
+        /// Bundle OpenAsar too
+        #[arg(long)]
+        pub openasar: bool,
+        /// Bundle an extension from moonlight's extension repo, by id
+        #[arg(short, long = "extension", value_name = "ID")]
+        pub extensions: Vec<String>,
+    }
+}
+
+pub(super) mod rollback {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        /// The version to go back to, the one before the current if left out
+        #[arg(index = 1, value_name = "VERSION")]
+        pub version: Option<String>,
+        /// The branch whose moonlight root to roll back
+        #[arg(short, long, default_value = "stable", value_parser(["stable", "ptb", "canary", "development"]))]
+        pub branch: String,
+        /// List the versions kept in the root instead
+        #[arg(short, long, conflicts_with = "version")]
+        pub list: bool,
+    }
+}
+
+pub(super) mod purge {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        pub yes: bool,
+    }
+}
+
+pub(super) mod asar {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    pub struct Args {
+        #[arg(index = 1, value_parser(["list", "extract", "pack"]), value_name = "ACTION")]
+        pub action: String,
+        /// The archive to list or extract, or the folder to pack
+        #[arg(index = 2, value_name = "SOURCE")]
+        pub source: String,
+        /// Where to extract or pack to
+        #[arg(index = 3, value_name = "DEST", required_if_eq_any([("action", "extract"), ("action", "pack")]))]
+        pub dest: Option<String>,
     }
 }
@@ -6,4 +6,15 @@ pub enum Subcommand {
     Down(args::down::Args),
     Dev(args::dev::Args),
     Openasar(args::openasar::Args),
+    Restore(args::restore::Args),
+    Verify(args::verify::Args),
+    Doctor(args::doctor::Args),
+    Adopt(args::adopt::Args),
+    Relocate(args::relocate::Args),
+    Config(args::config::Args),
+    History(args::history::Args),
+    Fetch(args::fetch::Args),
+    Rollback(args::rollback::Args),
+    Purge(args::purge::Args),
+    Asar(args::asar::Args),
 }
\ No newline at end of file
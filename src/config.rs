@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::discord::DiscordKind;
+use crate::moonlight::Channel;
+use crate::platform::{env, Platform};
+
+/// Settings for one Discord branch, anything left out falls back to the
+/// defaults shared by every branch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Branch {
+    pub channel: Option<Channel>,
+    pub root: Option<PathBuf>,
+    /// The stable release this branch is pinned to, a tag or a semver range.
+    pub version: Option<String>,
+}
+
+pub const DEFAULT_REPO: &str = "moonlight-mod/moonlight";
+pub const DEFAULT_GITHUB_API: &str = "https://api.github.com";
+pub const DEFAULT_NIGHTLY_URL: &str = "https://moonlight-mod.github.io/moonlight";
+pub const DEFAULT_GIT_URL: &str = "https://github.com/moonlight-mod/moonlight";
+
+/// Where moonlight is fetched from and how, for forks, mirrors and networks
+/// that need a proxy or their own CA.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Network {
+    /// The GitHub `owner/repo` stable releases come from.
+    pub repo: Option<String>,
+    pub github_api: Option<String>,
+    /// Serves nightly's `ref`, `dist.tar.gz` and `dist.tar.gz.sha256`.
+    pub nightly_url: Option<String>,
+    /// What the git channel clones.
+    pub git_url: Option<String>,
+    /// Every request goes through this, e.g. `http://proxy.example:3128`.
+    pub proxy: Option<String>,
+    /// PEM certificates to trust on top of the system's, for the git
+    /// channel's clones too.
+    pub ca_certs: Vec<PathBuf>,
+    /// Sent to the GitHub API for its higher rate limit. `MOON_GITHUB_TOKEN`
+    /// or `GITHUB_TOKEN` take precedence, but only for GitHub's own API.
+    pub github_token: Option<String>,
+}
+
+impl Network {
+    #[inline(always)]
+    pub fn repo(&self) -> &str {
+        self.repo.as_deref().unwrap_or(DEFAULT_REPO)
+    }
+
+    #[inline(always)]
+    pub fn github_api(&self) -> &str {
+        self.github_api
+            .as_deref()
+            .unwrap_or(DEFAULT_GITHUB_API)
+            .trim_end_matches('/')
+    }
+
+    #[inline(always)]
+    pub fn nightly_url(&self) -> &str {
+        self.nightly_url
+            .as_deref()
+            .unwrap_or(DEFAULT_NIGHTLY_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The token to send to `github_api`. One from the environment, like a
+    /// CI's `GITHUB_TOKEN`, is only ever sent to GitHub itself, never to a
+    /// mirror or stand-in configured in its place.
+    #[inline(always)]
+    pub fn github_token(&self) -> Option<String> {
+        ["MOON_GITHUB_TOKEN", "GITHUB_TOKEN"]
+            .into_iter()
+            .filter(|_| self.github_api() == DEFAULT_GITHUB_API)
+            .find_map(|var| std::env::var(var).ok().filter(|token| !token.is_empty()))
+            .or_else(|| self.github_token.clone())
+    }
+
+    #[inline(always)]
+    pub fn git_url(&self) -> &str {
+        self.git_url.as_deref().unwrap_or(DEFAULT_GIT_URL)
+    }
+}
+
+/// moon's own settings, kept in `config.yml` in moon's data dir.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Keyed by the `--branch` name.
+    pub branches: BTreeMap<String, Branch>,
+    /// How many downloaded moonlight versions each root keeps around for
+    /// `moon rollback`, on top of the active and previous ones.
+    pub keep_versions: Option<usize>,
+    pub network: Network,
+}
+
+/// Versions kept per root when the config doesn't say.
+pub const DEFAULT_KEEP_VERSIONS: usize = 3;
+
+impl Config {
+    #[inline(always)]
+    pub fn path() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("config.yml")
+    }
+
+    /// Loads the config, an absent file is the same as an empty one.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(Self::path()) {
+            Ok(contents) => Ok(serde_yaml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("yml.tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // it can hold a GitHub token, so it's never readable by others
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&tmp)?
+            .write_all(serde_yaml::to_string(self)?.as_bytes())?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn branch(&self, kind: DiscordKind) -> Branch {
+        self.branches
+            .get(kind.branch_name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn set_branch(&mut self, kind: DiscordKind, branch: Branch) {
+        if branch == Branch::default() {
+            self.branches.remove(kind.branch_name());
+        } else {
+            self.branches.insert(kind.branch_name().to_string(), branch);
+        }
+    }
+
+    /// The root every branch without its own settings shares.
+    #[inline(always)]
+    pub fn default_root() -> PathBuf {
+        PathBuf::from(env("MOONLIGHT_ROOT", &Platform::conf_dir()))
+    }
+
+    #[inline(always)]
+    pub fn keep_versions(&self) -> usize {
+        self.keep_versions.unwrap_or(DEFAULT_KEEP_VERSIONS)
+    }
+
+    #[inline(always)]
+    pub fn channel(&self, kind: DiscordKind) -> Option<Channel> {
+        self.branch(kind).channel
+    }
+
+    #[inline(always)]
+    pub fn version(&self, kind: DiscordKind) -> Option<String> {
+        self.branch(kind).version
+    }
+
+    /// The moonlight root `kind` is injected with. A branch with its own
+    /// channel but no root gets `<default root>-<branch>` beside
+    /// the default one, so its dist never overwrites another branch's and
+    /// nothing done to the default root reaches it.
+    pub fn root(&self, kind: DiscordKind) -> PathBuf {
+        match self.branch(kind).root {
+            Some(root) => root,
+            None if self.derives_root(kind) => Self::derived_root(&Self::default_root(), kind),
+            None => Self::default_root(),
+        }
+    }
+
+    /// Whether `kind`'s root is the one derived from the default root.
+    #[inline(always)]
+    pub fn derives_root(&self, kind: DiscordKind) -> bool {
+        let branch = self.branch(kind);
+        branch.root.is_none() && branch.channel.is_some()
+    }
+
+    /// `<default_root>-<branch>`, beside `default_root`.
+    #[inline(always)]
+    pub fn derived_root(default_root: &Path, kind: DiscordKind) -> PathBuf {
+        let mut name = default_root.file_name().unwrap_or_default().to_os_string();
+        name.push(format!("-{}", kind.branch_name()));
+        default_root.with_file_name(name)
+    }
+}
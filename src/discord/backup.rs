@@ -0,0 +1,138 @@
+use log as l;
+use std::{error::Error, fs, path::PathBuf};
+
+use super::foreign::ForeignMod;
+use super::DiscordInstall;
+use crate::platform::sha256_file;
+use crate::Platform;
+
+/// A pristine copy of Discord's app.asar for one install and host version,
+/// kept under moon's data dir.
+#[derive(Debug)]
+pub struct Backup {
+    pub host_version: String,
+    pub asar: PathBuf,
+    pub sha256: String,
+}
+
+impl Backup {
+    #[inline(always)]
+    pub fn root() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("backups")
+    }
+
+    #[inline(always)]
+    pub fn dir(install: &DiscordInstall) -> PathBuf {
+        Self::root().join(install.install_id())
+    }
+
+    #[inline(always)]
+    fn load(dir: PathBuf) -> Option<Self> {
+        let asar = dir.join("app.asar");
+        let sha256 = fs::read_to_string(dir.join("app.asar.sha256")).ok()?;
+        if !asar.is_file() {
+            return None;
+        }
+        Some(Self {
+            host_version: dir.file_name()?.to_string_lossy().to_string(),
+            asar,
+            sha256: sha256.trim().to_string(),
+        })
+    }
+
+    /// The backup for the install's current host version, or the newest one
+    /// there is if the host version can't be told.
+    pub fn find(install: &DiscordInstall) -> Option<Self> {
+        let dir = Self::dir(install);
+        if let Some(version) = &install.host_version {
+            return Self::load(dir.join(version));
+        }
+        let mut dirs = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .collect::<Vec<_>>();
+        dirs.sort_by_key(|e| e.metadata().and_then(|m| m.modified()).ok());
+        l::warn!("Host version of {:?} is unknown, using the newest backup", install.path);
+        Self::load(dirs.last()?.path())
+    }
+
+    pub fn verify(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(sha256_file(&self.asar)? == self.sha256)
+    }
+
+    /// Backs up the install's original app.asar, wherever it currently lives.
+    /// Does nothing if a good backup for this host version already exists.
+    /// Backups for other host versions are pruned once there's a good one for
+    /// this version, and only then.
+    pub fn create(install: &DiscordInstall) -> Result<Option<Self>, Box<dyn Error>> {
+        let version = match &install.host_version {
+            Some(version) => version.clone(),
+            None => {
+                l::warn!("Can't tell the host version of {:?}, not backing up", install.path);
+                return Ok(None);
+            }
+        };
+        if let Some(existing) = Self::find(install) {
+            if existing.verify()? {
+                l::debug!("Backup for {} already exists", version);
+                Self::prune(install)?;
+                return Ok(Some(existing));
+            }
+            l::warn!("Backup for {} is damaged, replacing it", version);
+        }
+
+        let resources = install.resources_dir();
+        let original = [resources.join("_app.asar"), resources.join("app.asar")]
+            .into_iter()
+            .find(|p| p.is_file());
+        let original = match original {
+            Some(original) => original,
+            None => {
+                l::warn!("No app.asar to back up in {:?}", resources);
+                return Ok(None);
+            }
+        };
+
+        if let Some(name) = ForeignMod::identify_asar(&original) {
+            l::warn!("{:?} is {:?}, not Discord's own, not backing up", original, name);
+            return Ok(None);
+        }
+
+        let dir = Self::dir(install).join(&version);
+        fs::create_dir_all(&dir)?;
+        let asar = dir.join("app.asar");
+        let tmp = dir.join("app.asar.tmp");
+        fs::copy(&original, &tmp)?;
+        let sha256 = sha256_file(&tmp)?;
+        fs::rename(&tmp, &asar)?;
+        fs::write(dir.join("app.asar.sha256"), &sha256)?;
+        l::info!("Backed up {:?} ({})", original, sha256);
+
+        Self::prune(install)?;
+        Ok(Some(Self {
+            host_version: version,
+            asar,
+            sha256,
+        }))
+    }
+
+    /// Drops backups for host versions other than the one installed now.
+    pub fn prune(install: &DiscordInstall) -> Result<(), Box<dyn Error>> {
+        let current = match &install.host_version {
+            Some(version) => version,
+            None => return Ok(()),
+        };
+        let dir = Self::dir(install);
+        if !dir.exists() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+            if entry.file_name().to_string_lossy() != current.as_str() {
+                l::info!("Pruning backup for host version {:?}", entry.file_name());
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::Path, path::PathBuf};
+
+use super::journal::Step;
+use super::DiscordInstall;
+use super::DiscordKind;
+use crate::asar::Asar;
+use crate::platform::env;
+
+/// Where moon parks a foreign loader it chain-loads through.
+pub const CHAIN_APP: &str = "_foreign_app";
+pub const CHAIN_ASAR: &str = "_foreign.asar";
+
+/// What BetterDiscord's desktop core index.js looks like untouched.
+pub const DESKTOP_CORE_INDEX: &str = "module.exports = require('./core.asar');";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ForeignMod {
+    Vencord,
+    BetterDiscord,
+    Replugged,
+    OpenAsar,
+    Unknown,
+}
+
+/// Where a foreign mod hooks into Discord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Hook {
+    /// Its own resources/app folder, like moon's.
+    AppFolder,
+    /// Its own app.asar in place of Discord's.
+    Asar,
+    /// A patched modules/discord_desktop_core/index.js.
+    DesktopCore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Foreign {
+    pub name: ForeignMod,
+    pub hook: Hook,
+    /// Already parked by moon and loaded through moonlight.
+    pub chained: bool,
+}
+
+/// What to do when `inject` finds another mod in the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ForeignPolicy {
+    Refuse,
+    Uninstall,
+    Chain,
+}
+
+impl std::str::FromStr for ForeignPolicy {
+    type Err = String;
+
+    #[inline(always)]
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "refuse" => Ok(ForeignPolicy::Refuse),
+            "uninstall" => Ok(ForeignPolicy::Uninstall),
+            "chain" => Ok(ForeignPolicy::Chain),
+            _ => Err(format!(
+                "Invalid foreign policy {:?}, expected refuse, uninstall or chain",
+                policy
+            )),
+        }
+    }
+}
+
+impl ForeignMod {
+    #[inline(always)]
+    fn identify(source: &str) -> Option<Self> {
+        let source = source.to_lowercase();
+        [
+            ("openasar", ForeignMod::OpenAsar),
+            ("vencord", ForeignMod::Vencord),
+            ("replugged", ForeignMod::Replugged),
+            ("betterdiscord", ForeignMod::BetterDiscord),
+        ]
+        .into_iter()
+        .find(|(marker, _)| source.contains(marker))
+        .map(|(_, name)| name)
+    }
+
+    /// Identifies a loader folder by its package.json and entry point. A
+    /// folder that isn't moon's and can't be told apart is `Unknown`.
+    pub fn identify_app(app: &Path) -> Self {
+        let package = fs::read_to_string(app.join("package.json")).unwrap_or_default();
+        let main = serde_json::from_str::<serde_json::Value>(&package)
+            .ok()
+            .and_then(|p| p.get("main")?.as_str().map(|m| m.to_string()))
+            .unwrap_or("index.js".to_string());
+        let entry = fs::read_to_string(app.join(main)).unwrap_or_default();
+        Self::identify(&package)
+            .or(Self::identify(&entry))
+            .unwrap_or(ForeignMod::Unknown)
+    }
+
+    /// Identifies an asar by its package.json and entry point. Discord's own
+    /// asar isn't foreign, so this is `None` for it.
+    pub fn identify_asar(asar: &Path) -> Option<Self> {
+        let archive = Asar::open(asar).ok()?;
+        let package = String::from_utf8(archive.read("package.json").ok()?).ok()?;
+        let main = serde_json::from_str::<serde_json::Value>(&package)
+            .ok()
+            .and_then(|p| p.get("main")?.as_str().map(|m| m.to_string()))
+            .unwrap_or("index.js".to_string());
+        let entry = archive
+            .read(main.trim_start_matches("./"))
+            .map(|e| String::from_utf8_lossy(&e).to_string())
+            .unwrap_or_default();
+        Self::identify(&package).or(Self::identify(&entry))
+    }
+}
+
+impl DiscordInstall {
+    /// Discord's desktop core index.js, which BetterDiscord patches.
+    pub fn desktop_core(&self) -> Option<PathBuf> {
+        #[cfg(target_os = "windows")]
+        {
+            let modules = fs::read_dir(self.path.join("modules")).ok()?;
+            for module in modules.filter_map(|m| m.ok()) {
+                let index = module
+                    .path()
+                    .join("discord_desktop_core")
+                    .join("index.js");
+                if module
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("discord_desktop_core")
+                    && index.exists()
+                {
+                    return Some(index);
+                }
+            }
+            None
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let index = self
+                .user_data_dir()
+                .join(self.host_version.as_ref()?)
+                .join("modules/discord_desktop_core/index.js");
+            index.exists().then_some(index)
+        }
+    }
+
+    /// Electron's appData folder as this install sees it, which for Flatpak
+    /// is inside the sandbox's own home.
+    fn app_data_dir(&self) -> PathBuf {
+        #[cfg(target_os = "windows")]
+        return PathBuf::from(env("APPDATA", &env("USERPROFILE", "./")));
+        #[cfg(target_os = "macos")]
+        return PathBuf::from(env("HOME", "~/")).join("Library/Application Support");
+        #[cfg(target_os = "linux")]
+        return if self.flatpak != super::Flatpak::Not {
+            PathBuf::from(env("HOME", "~/")).join(".var/app/com.discordapp.Discord/config")
+        } else {
+            PathBuf::from(env("XDG_CONFIG_HOME", &format!("{}/.config", env("HOME", "~/"))))
+        };
+    }
+
+    /// Where Discord keeps its settings.json and, outside Windows, its modules.
+    pub fn user_data_dir(&self) -> PathBuf {
+        let folder = match self.kind {
+            DiscordKind::Stable => "discord",
+            DiscordKind::Ptb => "discordptb",
+            DiscordKind::Canary => "discordcanary",
+            DiscordKind::Development => "discorddevelopment",
+        };
+        self.app_data_dir().join(folder)
+    }
+
+    /// Where moonlight loaded into this install looks for `<id>.asar`
+    /// extensions, shared by every branch.
+    pub fn moonlight_extensions_dir(&self) -> PathBuf {
+        self.app_data_dir().join("moonlight-mod").join("extensions")
+    }
+
+    pub(super) fn detect_foreign(&self) -> Vec<Foreign> {
+        let resources = self.resources_dir();
+        let mut found = vec![];
+
+        let chained_app = resources.join(CHAIN_APP);
+        let app = resources.join("app");
+        if chained_app.is_dir() {
+            found.push(Foreign {
+                name: ForeignMod::identify_app(&chained_app),
+                hook: Hook::AppFolder,
+                chained: true,
+            });
+        } else if app.is_dir() && self.injector_target().is_none() {
+            found.push(Foreign {
+                name: ForeignMod::identify_app(&app),
+                hook: Hook::AppFolder,
+                chained: false,
+            });
+        }
+
+        let chained_asar = resources.join(CHAIN_ASAR);
+        let asar = resources.join("app.asar");
+        if chained_asar.is_file() {
+            if let Some(name) = ForeignMod::identify_asar(&chained_asar) {
+                found.push(Foreign {
+                    name,
+                    hook: Hook::Asar,
+                    chained: true,
+                });
+            }
+        } else if asar.is_file() {
+            if let Some(name) = ForeignMod::identify_asar(&asar) {
+                found.push(Foreign {
+                    name,
+                    hook: Hook::Asar,
+                    chained: false,
+                });
+            }
+        }
+
+        if let Some(index) = self.desktop_core() {
+            let source = fs::read_to_string(index).unwrap_or_default();
+            if source.trim() != DESKTOP_CORE_INDEX {
+                if let Some(name) = ForeignMod::identify(&source) {
+                    found.push(Foreign {
+                        name,
+                        hook: Hook::DesktopCore,
+                        chained: false,
+                    });
+                }
+            }
+        }
+
+        // OpenAsar stands in for Discord's asar rather than hooking into it,
+        // so it's tracked by is_openasar and kept through inject and uninject
+        found.retain(|f| f.name != ForeignMod::OpenAsar);
+        found
+    }
+
+    /// Plans dealing with the foreign mods found, assuming moon's own
+    /// injection has already been planned away. Returns the steps, what
+    /// moonlight should load instead of Discord's asar and whether Discord's
+    /// app.asar still needs moving out of the way.
+    pub(super) fn plan_foreign(
+        &self,
+        policy: ForeignPolicy,
+    ) -> Result<(Vec<Step>, &'static str, bool), Box<dyn Error>> {
+        let resources = self.resources_dir();
+        let app = resources.join("app");
+        let app_asar = resources.join("app.asar");
+        let _app_asar = resources.join("_app.asar");
+        let mut steps = vec![];
+        let mut target = "_app.asar";
+        let mut move_asar = true;
+
+        let unchained = self
+            .foreign
+            .iter()
+            .filter(|f| !f.chained)
+            .map(|f| format!("{:?}", f.name))
+            .collect::<Vec<_>>();
+        if policy == ForeignPolicy::Refuse && !unchained.is_empty() {
+            return Err(format!(
+                "Discord {:?} already has {} installed, rerun with --foreign uninstall to remove it or --foreign chain to load it through moonlight",
+                self.kind,
+                unchained.join(", ")
+            )
+            .into());
+        }
+
+        for foreign in &self.foreign {
+            let policy = if foreign.chained && policy == ForeignPolicy::Refuse {
+                ForeignPolicy::Chain
+            } else {
+                policy
+            };
+            match (policy, foreign.hook) {
+                (ForeignPolicy::Chain, Hook::AppFolder) => {
+                    steps.push(Step::Rename {
+                        from: app.clone(),
+                        to: resources.join(CHAIN_APP),
+                    });
+                    target = CHAIN_APP;
+                    move_asar = false;
+                }
+                (ForeignPolicy::Chain, Hook::Asar) => {
+                    steps.push(Step::Rename {
+                        from: app_asar.clone(),
+                        to: resources.join(CHAIN_ASAR),
+                    });
+                    target = CHAIN_ASAR;
+                    move_asar = false;
+                }
+                (ForeignPolicy::Uninstall, Hook::AppFolder) => {
+                    steps.push(Step::Remove {
+                        trash: Step::trash_for(&app),
+                        path: app.clone(),
+                    });
+                    let replugged_asar = resources.join("app.orig.asar");
+                    if replugged_asar.exists() {
+                        steps.push(Step::Rename {
+                            from: replugged_asar,
+                            to: app_asar.clone(),
+                        });
+                    } else if !app_asar.exists() && _app_asar.exists() {
+                        steps.push(Step::Rename {
+                            from: _app_asar.clone(),
+                            to: app_asar.clone(),
+                        });
+                    }
+                }
+                (ForeignPolicy::Uninstall, Hook::Asar) => {
+                    steps.push(Step::Remove {
+                        trash: Step::trash_for(&app_asar),
+                        path: app_asar.clone(),
+                    });
+                    steps.push(Step::Rename {
+                        from: _app_asar.clone(),
+                        to: app_asar.clone(),
+                    });
+                }
+                (ForeignPolicy::Uninstall, Hook::DesktopCore) => {
+                    if let Some(index) = self.desktop_core() {
+                        steps.push(Step::Write {
+                            backup: Some(Step::backup_for(&index)),
+                            path: index,
+                            contents: DESKTOP_CORE_INDEX.to_string(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((steps, target, move_asar))
+    }
+}
@@ -1 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
 
+use super::install::INJECTOR_1;
+use super::DiscordInstall;
+
+/// First line of every injector.js moon writes, so it can tell its own
+/// apart from ones written by other tools or older versions of itself.
+pub(super) const INJECTOR_MARKER: &str = "// Managed by moon, see `moon verify`\n";
+
+/// Who wrote the moonlight loader found in an install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InjectorFormat {
+    Moon,
+    /// moon from before it marked its injector.js.
+    MoonLegacy,
+    /// The official moonlight installer, or anything else that loads a
+    /// moonlight injector.js.
+    Installer,
+}
+
+#[derive(Debug, Clone)]
+pub struct Injector {
+    pub format: InjectorFormat,
+    pub file: PathBuf,
+    /// The moonlight dist/injector.js that gets loaded.
+    pub target: PathBuf,
+}
+
+impl Injector {
+    pub fn parse(source: &str, file: PathBuf) -> Option<Self> {
+        let (format, target) = if let Some(rest) = source
+            .strip_prefix(INJECTOR_MARKER)
+            .and_then(|s| s.strip_prefix(INJECTOR_1))
+        {
+            (InjectorFormat::Moon, rest[..rest.find('"')?].to_string())
+        } else if let Some(rest) = source.strip_prefix(INJECTOR_1) {
+            (InjectorFormat::MoonLegacy, rest[..rest.find('"')?].to_string())
+        } else {
+            (InjectorFormat::Installer, Self::find_injector_literal(source)?)
+        };
+        Some(Self {
+            format,
+            file,
+            target: PathBuf::from(target),
+        })
+    }
+
+    /// The first JS string literal in `source` naming an injector.js.
+    #[inline(always)]
+    fn find_injector_literal(source: &str) -> Option<String> {
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            if !matches!(c, '"' | '\'' | '`') {
+                continue;
+            }
+            let mut literal = String::new();
+            while let Some(next) = chars.next() {
+                match next {
+                    '\\' => literal.extend(chars.next()),
+                    q if q == c => break,
+                    other => literal.push(other),
+                }
+            }
+            if literal.ends_with("injector.js") {
+                return Some(literal);
+            }
+        }
+        None
+    }
+
+    /// The root holding the dist this loads, `<root>/dist/injector.js`.
+    #[inline(always)]
+    pub fn moonlight_root(&self) -> Option<PathBuf> {
+        Some(self.target.parent()?.parent()?.to_path_buf())
+    }
+}
+
+impl DiscordInstall {
+    /// Finds the moonlight loader in resources/app, or in the app.asar
+    /// folder very old injections used.
+    pub fn injector(&self) -> Option<Injector> {
+        let resources = self.resources_dir();
+        for folder in [resources.join("app"), resources.join("app.asar")] {
+            if !folder.is_dir() {
+                continue;
+            }
+            let main = fs::read_to_string(folder.join("package.json"))
+                .ok()
+                .and_then(|p| serde_json::from_str::<serde_json::Value>(&p).ok())
+                .and_then(|p| p.get("main")?.as_str().map(|m| m.to_string()));
+            let candidates = main
+                .into_iter()
+                .chain(["injector.js".to_string(), "index.js".to_string()]);
+            for candidate in candidates {
+                let file = folder.join(candidate.trim_start_matches("./"));
+                if let Some(injector) = fs::read_to_string(&file)
+                    .ok()
+                    .and_then(|source| Injector::parse(&source, file))
+                {
+                    return Some(injector);
+                }
+            }
+        }
+        None
+    }
+}
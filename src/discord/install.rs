@@ -3,20 +3,28 @@ use concat_string::concat_string; // you looove microoptimizations
 use log as l;
 #[cfg(target_os = "windows")]
 use path_slash::PathBufExt as _;
-use std::{error::Error, path::PathBuf};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
 
+use super::backup::Backup;
+use super::injected::{InjectorFormat, INJECTOR_MARKER};
+use super::foreign::{Foreign, ForeignPolicy, Hook, CHAIN_APP, CHAIN_ASAR};
+use super::journal::{Journal, Operation, Step};
 use super::DiscordKind;
 #[cfg(target_os = "linux")]
 use super::Flatpak;
 
-const PACKAGE_JSON: &str = r#"{
+pub(super) const PACKAGE_JSON: &str = r#"{
     "name": "discord",
     "main": "./injector.js",
     "private": true
 }"#;
 
-const INJECTOR_1: &str = r#"require(""#;
-const INJECTOR_2: &str = r#"").inject(require("path").resolve(__dirname, "../_app.asar"));"#;
+pub(super) const INJECTOR_1: &str = r#"require(""#;
+pub(super) const INJECTOR_2: &str = r#"").inject(require("path").resolve(__dirname, "../"#;
+pub(super) const INJECTOR_3: &str = r#""));"#;
 
 #[derive(Debug)]
 pub struct DiscordInstall {
@@ -24,6 +32,11 @@ pub struct DiscordInstall {
     pub path: PathBuf,
     pub injected: bool,
     pub is_openasar: bool,
+    /// OpenAsar's version, when `is_openasar`.
+    pub openasar_version: Option<String>,
+    pub host_version: Option<String>,
+    /// Other client mods found hooked into this install.
+    pub foreign: Vec<Foreign>,
     #[cfg(target_os = "linux")]
     pub flatpak: Flatpak,
     #[cfg(target_os = "linux")]
@@ -113,21 +126,23 @@ impl DiscordInstall {
             };
 
             let injected = {
-                if (sys_electron && path.join("_app.asar.unpacked").exists())
-                    || (path.join("app").exists()
-                        && path.join("resources").join("app.asar").is_dir())
-                {
-                    true
+                let resources = if sys_electron {
+                    path.clone()
                 } else {
-                    false
-                }
+                    path.join("resources")
+                };
+                resources.join("app").exists()
+                    || resources.join("app.asar").is_dir()
+                    || (sys_electron && path.join("_app.asar.unpacked").exists())
             };
 
             let is_valid = {
+                // a lone _app.asar is a broken injection, still worth restoring
                 if sys_electron {
                     path.join("app.asar").exists()
                 } else {
                     path.join("resources").join("app.asar").exists()
+                        || path.join("resources").join("_app.asar").exists()
                 }
             };
 
@@ -137,16 +152,30 @@ impl DiscordInstall {
         match is_valid {
             Some(true) => {
                 l::info!("Found valid Discord install at {:?}", path);
-                Some(Self {
+                let mut install = Self {
                     kind,
                     path,
                     injected,
-                    is_openasar: false, // TODO: openasar detection
+                    is_openasar: false,
+                    openasar_version: None,
+                    host_version: None,
+                    foreign: vec![],
                     #[cfg(target_os = "linux")]
                     flatpak,
                     #[cfg(target_os = "linux")]
                     is_sys_electron,
-                })
+                };
+                install.host_version = install.detect_host_version();
+                install.foreign = install.detect_foreign();
+                install.openasar_version = install.detect_openasar();
+                install.is_openasar = install.openasar_version.is_some();
+                if let Some(version) = &install.openasar_version {
+                    l::info!("Found OpenAsar {} in place of Discord's asar", version);
+                }
+                for foreign in &install.foreign {
+                    l::warn!("Found {:?} hooked in via {:?}", foreign.name, foreign.hook);
+                }
+                Some(install)
             }
             Some(false) => {
                 l::info!("Found invalid Discord install at {:?}", path);
@@ -159,31 +188,71 @@ impl DiscordInstall {
         }
     }
 
-    pub async fn inject(&self, moonlight_root: &PathBuf) -> Result<(), Box<dyn Error>> {
-        if self.injected {
+    pub async fn inject(&self, moonlight_root: &Path) -> Result<(), Box<dyn Error>> {
+        self.inject_with(moonlight_root, ForeignPolicy::Refuse).await
+    }
+
+    /// Injects, handling any other client mod found according to `policy`.
+    pub async fn inject_with(
+        &self,
+        moonlight_root: &Path,
+        policy: ForeignPolicy,
+    ) -> Result<(), Box<dyn Error>> {
+        let steps = self.plan_inject(moonlight_root, policy)?;
+        if let Err(e) = Backup::create(self) {
+            l::warn!("Failed to back up the original app.asar: {}", e);
+        }
+        l::info!("Injecting Discord {:?} ({} steps)", self.kind, steps.len());
+        Journal::run(self.kind, &self.path, Operation::Inject, steps).await
+    }
+
+    /// The steps `inject_with` would run, without touching anything.
+    pub fn plan_inject(
+        &self,
+        moonlight_root: &Path,
+        policy: ForeignPolicy,
+    ) -> Result<Vec<Step>, Box<dyn Error>> {
+        let mut steps = vec![];
+        if self.injected_by_moon() {
             l::warn!(
                 "Discord install at {:?} is already injected, uninjecting first",
                 self.path
             );
-            self.uninject().await?;
-            l::info!("Reinjecting Discord {:?}", self.kind)
+            steps.extend(self.unmove_discord_items());
         }
 
-        self.move_discord_items().await?;
-        l::info!("Writing injection files");
-        self.write_injection_files(moonlight_root).await?;
-        Ok(())
+        let (foreign_steps, target, move_asar) = self.plan_foreign(policy)?;
+        steps.extend(foreign_steps);
+        if move_asar {
+            steps.extend(self.move_discord_items());
+        }
+        steps.extend(self.write_injection_files(moonlight_root, target, false));
+        Ok(steps)
     }
 
     pub async fn uninject(&self) -> Result<(), Box<dyn Error>> {
-        if !self.injected {
-            l::warn!("Discord install at {:?} is not injected", self.path);
+        let steps = self.plan_uninject()?;
+        if steps.is_empty() {
             return Ok(());
         }
         l::info!("Resetting Discord {:?}", self.kind);
-        self.unmove_discord_items().await?;
-        self.rm_injection_files().await?;
-        Ok(())
+        Journal::run(self.kind, &self.path, Operation::Uninject, steps).await
+    }
+
+    /// The steps `uninject` would run, without touching anything.
+    pub fn plan_uninject(&self) -> Result<Vec<Step>, Box<dyn Error>> {
+        if !self.injected {
+            l::warn!("Discord install at {:?} is not injected", self.path);
+            return Ok(vec![]);
+        }
+        if !self.injected_by_moon() {
+            return Err(format!(
+                "Discord install at {:?} was injected by something other than moon, not touching it",
+                self.path
+            )
+            .into());
+        }
+        Ok(self.unmove_discord_items())
     }
 
     #[inline(always)]
@@ -191,48 +260,181 @@ impl DiscordInstall {
         &self,
         moonlight_root: &PathBuf,
     ) -> Result<(), Box<dyn Error>> {
-        if !self.injected {
+        if !self.injected_by_moon() {
             return self.inject(moonlight_root).await;
         }
+        if self.injector().map(|i| i.format) != Some(InjectorFormat::Moon) {
+            return self.adopt(Some(moonlight_root)).await;
+        }
         l::info!(
             "Modifying Moonlight root for Discord install at {:?}",
             self.path
         );
 
-        self.write_injection_files(moonlight_root).await?;
+        Journal::run(
+            self.kind,
+            &self.path,
+            Operation::Retarget,
+            self.plan_retarget(moonlight_root),
+        )
+        .await
+    }
 
-        Ok(())
+    /// The steps to point an install moon already manages at another root.
+    #[inline(always)]
+    pub fn plan_retarget(&self, moonlight_root: &Path) -> Vec<Step> {
+        self.write_injection_files(moonlight_root, self.chain_target(), true)
     }
+
+    /// Injected, and not by some other mod's own app folder.
     #[inline(always)]
-    async fn move_discord_items(&self) -> Result<(), Box<dyn Error>> {
-        use std::fs;
-        let mut root_path = self.path.clone();
-        #[cfg(target_os = "linux")]
-        {
-            if !self.is_sys_electron {
-                root_path = root_path.join("resources");
+    pub fn injected_by_moon(&self) -> bool {
+        self.injected
+            && !self
+                .foreign
+                .iter()
+                .any(|f| f.hook == Hook::AppFolder && !f.chained)
+    }
+
+    /// What the current injector.js should hand to moonlight's injector.
+    #[inline(always)]
+    fn chain_target(&self) -> &'static str {
+        let resources = self.resources_dir();
+        if resources.join(CHAIN_APP).exists() {
+            CHAIN_APP
+        } else if resources.join(CHAIN_ASAR).exists() {
+            CHAIN_ASAR
+        } else {
+            "_app.asar"
+        }
+    }
+
+    /// Puts Discord's original app.asar back from moon's backup, whatever
+    /// state the install is in.
+    pub async fn restore(&self) -> Result<(), Box<dyn Error>> {
+        let backup = match Backup::find(self) {
+            Some(backup) => backup,
+            None => return Err(format!("No backup found for {:?}", self.path).into()),
+        };
+        if !backup.verify()? {
+            return Err(format!("Backup at {:?} failed its checksum", backup.asar).into());
+        }
+        l::info!(
+            "Restoring Discord {:?} from backup of {}",
+            self.kind,
+            backup.host_version
+        );
+        let root_path = self.resources_dir();
+        let mut steps = vec![];
+        for name in ["app", "app.asar", "_app.asar"] {
+            let path = root_path.join(name);
+            if path.exists() {
+                steps.push(Step::Remove {
+                    trash: Step::trash_for(&path),
+                    path,
+                });
             }
         }
-        #[cfg(target_os = "windows")]
-        {
-            root_path = root_path.join("resources");
+        steps.push(Step::Copy {
+            from: backup.asar,
+            to: root_path.join("app.asar"),
+            existed: false,
+        });
+        Journal::run(self.kind, &self.path, Operation::Restore, steps).await
+    }
+
+    /// The moonlight injector.js the install loads, if it's injected with
+    /// moonlight by moon or anything else.
+    #[inline(always)]
+    pub fn injector_target(&self) -> Option<PathBuf> {
+        self.injector().map(|injector| injector.target)
+    }
+
+    /// Takes over an injection written by the moonlight installer or an
+    /// older moon, rewriting it the way moon does now. `moonlight_root`
+    /// overrides the root the old injection pointed at.
+    pub async fn adopt(&self, moonlight_root: Option<&PathBuf>) -> Result<(), Box<dyn Error>> {
+        let steps = self.plan_adopt(moonlight_root)?;
+        if let Err(e) = Backup::create(self) {
+            l::warn!("Failed to back up the original app.asar: {}", e);
         }
-        #[cfg(target_os = "macos")]
-        {
-            root_path = root_path.join("Contents/Resources");
+        Journal::run(self.kind, &self.path, Operation::Adopt, steps).await
+    }
+
+    /// The steps `adopt` would run, without touching anything.
+    pub fn plan_adopt(&self, moonlight_root: Option<&PathBuf>) -> Result<Vec<Step>, Box<dyn Error>> {
+        let injector = match self.injector() {
+            Some(injector) => injector,
+            None => {
+                return Err(format!(
+                    "Discord install at {:?} isn't injected with moonlight",
+                    self.path
+                )
+                .into())
+            }
+        };
+        let root = match moonlight_root.cloned().or(injector.moonlight_root()) {
+            Some(root) => root,
+            None => {
+                return Err(format!("Can't tell the moonlight root from {:?}", injector.file).into())
+            }
+        };
+        l::info!(
+            "Adopting {:?} injection of Discord {:?} (root {:?})",
+            injector.format,
+            self.kind,
+            root
+        );
+        let target = self.chain_target();
+        if target != "_app.asar" {
+            // a chain-loaded mod stays parked where it is, only the loader in
+            // app/ is rewritten, with backups of what's there
+            return Ok(self.write_injection_files(&root, target, true));
         }
+        // the old app/ goes to the trash, so the fresh one needs no backups
+        let mut steps = self.unmove_discord_items();
+        steps.extend(self.move_discord_items());
+        steps.extend(self.write_injection_files(&root, target, false));
+        Ok(steps)
+    }
 
-        l::debug!("Moving Discord items from {:?}", root_path);
-        let app_asar = root_path.join("app.asar");
-        let _app_asar = root_path.join("_app.asar");
-        if app_asar.exists() {
-            fs::rename(app_asar, _app_asar)?;
+    /// A stable name for this install that survives host updates, used to
+    /// key anything moon stores about it.
+    #[inline(always)]
+    pub fn install_id(&self) -> String {
+        #[cfg(target_os = "windows")]
+        let base = self.path.parent().unwrap_or(&self.path);
+        #[cfg(not(target_os = "windows"))]
+        let base = &self.path;
+        base.to_string_lossy()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    #[inline(always)]
+    fn detect_host_version(&self) -> Option<String> {
+        let build_info = self.resources_dir().join("build_info.json");
+        if let Ok(info) = std::fs::read_to_string(build_info) {
+            if let Some(version) = serde_json::from_str::<serde_json::Value>(&info)
+                .ok()
+                .and_then(|v| v.get("version")?.as_str().map(|s| s.to_string()))
+            {
+                return Some(version);
+            }
         }
-        Ok(())
+        #[cfg(target_os = "windows")]
+        return self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().replace("app-", ""));
+        #[cfg(not(target_os = "windows"))]
+        None
     }
+
     #[inline(always)]
-    async fn unmove_discord_items(&self) -> Result<(), Box<dyn Error>> {
-        use std::fs;
+    pub fn resources_dir(&self) -> PathBuf {
+        #[allow(unused_mut)]
         let mut root_path = self.path.clone();
         #[cfg(target_os = "linux")]
         {
@@ -240,7 +442,6 @@ impl DiscordInstall {
                 root_path = root_path.join("resources");
             }
         }
-
         #[cfg(target_os = "windows")]
         {
             root_path = root_path.join("resources");
@@ -249,100 +450,157 @@ impl DiscordInstall {
         {
             root_path = root_path.join("Contents/Resources");
         }
+        root_path
+    }
+
+    /// Plans moving Discord's own app.asar out of the way. Assumes the install
+    /// is clean, either already or after the steps planned before it.
+    #[inline(always)]
+    fn move_discord_items(&self) -> Vec<Step> {
+        let root_path = self.resources_dir();
+        l::debug!("Moving Discord items from {:?}", root_path);
         let app_asar = root_path.join("app.asar");
         let _app_asar = root_path.join("_app.asar");
-        if app_asar.exists() {
-            if app_asar.is_dir() {
-                fs::remove_dir_all(app_asar.clone())?;
-            } else if _app_asar.exists() {
-                fs::remove_file(app_asar.clone())?;
-            }
+        if app_asar.exists() || _app_asar.exists() {
+            vec![Step::Rename {
+                from: app_asar,
+                to: _app_asar,
+            }]
+        } else {
+            vec![]
         }
-        fs::rename(_app_asar, app_asar)?;
-        Ok(())
     }
 
+    /// Plans undoing moon's injection, putting back a chain-loaded mod if
+    /// there was one.
     #[inline(always)]
-    async fn write_injection_files(&self, moonlight_root: &PathBuf) -> Result<(), Box<dyn Error>> {
-        use std::fs;
-        let mut root_path = self.path.clone();
-        #[cfg(target_os = "linux")]
-        {
-            if !self.is_sys_electron {
-                root_path = root_path.join("resources");
-            }
+    fn unmove_discord_items(&self) -> Vec<Step> {
+        let root_path = self.resources_dir();
+        let mut steps = vec![];
+        let app = root_path.join("app");
+        let app_asar = root_path.join("app.asar");
+        let _app_asar = root_path.join("_app.asar");
+        if app.exists() {
+            steps.push(Step::Remove {
+                trash: Step::trash_for(&app),
+                path: app.clone(),
+            });
         }
-        #[cfg(target_os = "windows")]
-        {
-            root_path = root_path.join("resources");
+        let chained_app = root_path.join(CHAIN_APP);
+        if chained_app.exists() {
+            steps.push(Step::Rename {
+                from: chained_app,
+                to: app,
+            });
+            return steps;
         }
-        #[cfg(target_os = "macos")]
-        {
-            root_path = root_path.join("Contents/Resources");
+        if app_asar.is_dir() || (app_asar.exists() && _app_asar.exists()) {
+            steps.push(Step::Remove {
+                trash: Step::trash_for(&app_asar),
+                path: app_asar.clone(),
+            });
         }
-        root_path = root_path.join("app");
-        if !root_path.exists() {
-            fs::create_dir_all(&root_path)?;
+        let chained_asar = root_path.join(CHAIN_ASAR);
+        if chained_asar.exists() {
+            steps.push(Step::Rename {
+                from: chained_asar,
+                to: app_asar,
+            });
+        } else if _app_asar.exists() {
+            steps.push(Step::Rename {
+                from: _app_asar,
+                to: app_asar,
+            });
+        }
+        steps
+    }
+
+    /// Plans writing the loader into resources/app, handing `target` to
+    /// moonlight as the app to load. With `existing` set the folder is
+    /// assumed to already be there and its files are overwritten.
+    #[inline(always)]
+    fn write_injection_files(
+        &self,
+        moonlight_root: &Path,
+        target: &str,
+        existing: bool,
+    ) -> Vec<Step> {
+        let root_path = self.resources_dir().join("app");
+        let mut steps = vec![];
+        if !existing || !root_path.exists() {
+            steps.push(Step::CreateDir {
+                path: root_path.clone(),
+                existed: false,
+            });
         }
         l::debug!("Writing injection files to {:?}", root_path);
         let package_json = root_path.join("package.json");
-        fs::write(package_json, PACKAGE_JSON)?;
         let injector_js = root_path.join("injector.js");
         #[cfg(target_os = "windows")]
-        fs::write(
-            injector_js,
-            concat_string!(
-                INJECTOR_1,
-                moonlight_root
-                    .join("dist")
-                    .join("injector.js")
-                    .to_slash()
-                    .unwrap(),
-                INJECTOR_2
-            ),
-        )?;
+        let injector = concat_string!(
+            INJECTOR_MARKER,
+            INJECTOR_1,
+            moonlight_root
+                .join("dist")
+                .join("injector.js")
+                .to_slash()
+                .unwrap(),
+            INJECTOR_2,
+            target,
+            INJECTOR_3
+        );
         #[cfg(not(target_os = "windows"))]
-        fs::write(
-            injector_js,
-            concat_string!(
-                INJECTOR_1,
-                moonlight_root
-                    .join("dist")
-                    .join("injector.js")
-                    .to_string_lossy(),
-                INJECTOR_2
-            ),
-        )?;
-        Ok(())
-    }
-    #[inline(always)]
-    async fn rm_injection_files(&self) -> Result<(), Box<dyn Error>> {
-        use std::fs;
-        let mut root_path = self.path.clone();
-        #[cfg(target_os = "linux")]
-        {
-            if !self.is_sys_electron {
-                root_path = root_path.join("resources");
-            }
+        let injector = concat_string!(
+            INJECTOR_MARKER,
+            INJECTOR_1,
+            moonlight_root
+                .join("dist")
+                .join("injector.js")
+                .to_string_lossy(),
+            INJECTOR_2,
+            target,
+            INJECTOR_3
+        );
+        for (path, contents) in [
+            (package_json, PACKAGE_JSON.to_string()),
+            (injector_js, injector),
+        ] {
+            steps.push(Step::Write {
+                backup: (existing && path.exists()).then(|| Step::backup_for(&path)),
+                path,
+                contents,
+            });
         }
+        steps
+    }
+
+    pub fn is_running(&self) -> bool {
         #[cfg(target_os = "windows")]
-        {
-            root_path = root_path.join("resources");
-        }
+        return Platform::win_findbyname(&concat_string!(self.kind.to_string(), ".exe"));
         #[cfg(target_os = "macos")]
-        {
-            root_path = root_path.join("Contents/Resources");
-        }
-        root_path = root_path.join("app");
-        if root_path.exists() {
-            fs::remove_dir_all(&root_path)?;
-        }
-        let old_asar_path = &root_path.join("_app.asar");
-        let asar_path = &root_path.join("app.asar");
-        if old_asar_path.exists() {
-            fs::rename(old_asar_path, asar_path)?;
-        }
-        Ok(())
+        return Platform::cmd_is_ok(
+            vec![
+                "pgrep".to_owned(),
+                "-x".to_owned(),
+                self.path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+                    .replace(".app", ""),
+            ],
+            None,
+        );
+        #[cfg(target_os = "linux")]
+        return match self.flatpak {
+            Flatpak::Not => Platform::cmd_is_ok(
+                vec!["pgrep".to_owned(), "-x".to_owned(), self.kind.to_string()],
+                None,
+            ),
+            _ => Platform::cmd_output(vec!["flatpak".to_owned(), "ps".to_owned()], None)
+                .map(|ps| ps.contains("com.discordapp.Discord"))
+                .unwrap_or(false),
+        };
     }
 
     pub async fn kill(&self) -> Result<(), Box<dyn Error>> {
@@ -0,0 +1,623 @@
+use log as l;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::foreign::{CHAIN_APP, CHAIN_ASAR};
+use super::injected::Injector;
+use super::DiscordKind;
+use crate::lock::Lock;
+use crate::state::Strategy;
+use crate::Platform;
+
+/// A single filesystem operation done to a Discord install. Every step can be
+/// undone, so nothing is actually deleted until the whole transaction commits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Step {
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// `existed` is filled in as the step runs, a folder that was already
+    /// there is left alone on undo.
+    CreateDir {
+        path: PathBuf,
+        #[serde(default)]
+        existed: bool,
+    },
+    /// Copies `from` to `to`, which must not exist yet. `existed` is filled
+    /// in as the step runs, a `to` that was already there fails the step and
+    /// is left alone on undo.
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        #[serde(default)]
+        existed: bool,
+    },
+    /// `backup` is set when `path` already existed, the old file is parked
+    /// there until commit.
+    Write {
+        path: PathBuf,
+        contents: String,
+        backup: Option<PathBuf>,
+    },
+    /// Moves `path` to `trash`, which is only deleted on commit.
+    Remove {
+        path: PathBuf,
+        trash: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    Inject,
+    Uninject,
+    Retarget,
+    Restore,
+    Repair,
+    Adopt,
+    InstallOpenAsar,
+    RemoveOpenAsar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum State {
+    Pending,
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub step: Step,
+    pub state: State,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub kind: DiscordKind,
+    pub install: PathBuf,
+    pub operation: Operation,
+    pub entries: Vec<Entry>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Rename { from, to } => write!(f, "rename {:?} to {:?}", from, to),
+            Step::CreateDir { path, .. } => write!(f, "create {:?}", path),
+            Step::Copy { from, to, .. } => write!(f, "copy {:?} to {:?}", from, to),
+            Step::Write { path, contents, .. } => {
+                write!(f, "write {:?} ({} bytes)", path, contents.len())
+            }
+            Step::Remove { path, .. } => write!(f, "remove {:?}", path),
+        }
+    }
+}
+
+impl Step {
+    /// A spot next to `path` to park it in. Numbered, as one transaction
+    /// can remove the same path twice.
+    #[inline(always)]
+    pub fn trash_for(path: &Path) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        path.with_file_name(format!(
+            ".moon-trash-{}-{}",
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+    }
+
+    #[inline(always)]
+    pub fn backup_for(path: &Path) -> PathBuf {
+        path.with_file_name(format!(
+            "{}.moon-bak",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+    }
+
+    fn apply(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Step::Rename { from, to } => {
+                l::debug!("Renaming {:?} to {:?}", from, to);
+                fs::rename(from, to)?;
+            }
+            Step::CreateDir { path, .. } => {
+                l::debug!("Creating {:?}", path);
+                fs::create_dir_all(path)?;
+            }
+            Step::Copy { from, to, .. } => {
+                l::debug!("Copying {:?} to {:?}", from, to);
+                let mut to = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(to)?;
+                io::copy(&mut fs::File::open(from)?, &mut to)?;
+                to.sync_all()?;
+            }
+            Step::Write {
+                path,
+                contents,
+                backup,
+            } => {
+                l::debug!("Writing {:?}", path);
+                if let Some(backup) = backup {
+                    if path.exists() {
+                        remove_any(backup)?;
+                        fs::rename(path, backup)?;
+                    }
+                }
+                fs::write(path, contents)?;
+            }
+            Step::Remove { path, trash } => {
+                l::debug!("Removing {:?}", path);
+                remove_any(trash)?;
+                fs::rename(path, trash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts this step. Safe to call on a step that only partially ran.
+    fn undo(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Step::Rename { from, to } => {
+                if to.exists() && !from.exists() {
+                    l::debug!("Renaming {:?} back to {:?}", to, from);
+                    fs::rename(to, from)?;
+                }
+            }
+            Step::CreateDir { path, existed } => {
+                if !existed {
+                    l::debug!("Removing created {:?}", path);
+                    remove_any(path)?;
+                }
+            }
+            Step::Copy { to, existed, .. } => {
+                if !existed {
+                    l::debug!("Removing copied {:?}", to);
+                    remove_any(to)?;
+                }
+            }
+            Step::Write { path, backup, .. } => match backup {
+                Some(backup) => {
+                    if backup.exists() {
+                        l::debug!("Restoring {:?} from {:?}", path, backup);
+                        remove_any(path)?;
+                        fs::rename(backup, path)?;
+                    }
+                }
+                None => {
+                    l::debug!("Removing written {:?}", path);
+                    remove_any(path)?;
+                }
+            },
+            Step::Remove { path, trash } => {
+                if trash.exists() && !path.exists() {
+                    l::debug!("Restoring {:?} from {:?}", path, trash);
+                    fs::rename(trash, path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Notes down what undoing this step needs to know about how things were
+    /// before it, right before it runs.
+    #[inline(always)]
+    fn prepare(&mut self) {
+        match self {
+            Step::CreateDir { path, existed } => *existed = path.exists(),
+            Step::Copy { to, existed, .. } => *existed = to.exists() || to.is_symlink(),
+            _ => {}
+        }
+    }
+
+    /// The root and strategy an injector.js written by this step loads.
+    fn injected(&self) -> Option<(PathBuf, Strategy)> {
+        match self {
+            Step::Write { path, contents, .. } if path.ends_with("app/injector.js") => {
+                let root = Injector::parse(contents, path.clone())?.moonlight_root()?;
+                let strategy = if contents.contains(CHAIN_APP) || contents.contains(CHAIN_ASAR) {
+                    Strategy::Chain
+                } else {
+                    Strategy::AppFolder
+                };
+                Some((root, strategy))
+            }
+            _ => None,
+        }
+    }
+
+    /// Throws away whatever this step kept around for undoing it.
+    fn finish(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Step::Write {
+                backup: Some(backup),
+                ..
+            } => remove_any(backup),
+            Step::Remove { trash, .. } => remove_any(trash),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Journal {
+    #[inline(always)]
+    pub fn dir() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("journal")
+    }
+
+    #[inline(always)]
+    fn file_for(install: &Path) -> PathBuf {
+        let name = install
+            .to_string_lossy()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        Self::dir().join(format!("{}.json", name))
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        // write then rename, a torn journal is worse than no journal
+        let tmp = self.file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+
+    /// Runs `steps` against the install at `install`, journaling each one
+    /// before it's applied. On failure everything done so far is undone.
+    /// The install stays locked against other moon runs throughout, and the
+    /// outcome goes into moon's history either way.
+    pub async fn run(
+        kind: DiscordKind,
+        install: &Path,
+        operation: Operation,
+        steps: Vec<Step>,
+    ) -> Result<(), Box<dyn Error>> {
+        let injected = steps.iter().find_map(Step::injected);
+        let result = Self::transact(kind, install, operation, steps).await;
+        crate::state::State::record_transaction(kind, install, operation, injected, &result).await;
+        result
+    }
+
+    async fn transact(
+        kind: DiscordKind,
+        install: &Path,
+        operation: Operation,
+        steps: Vec<Step>,
+    ) -> Result<(), Box<dyn Error>> {
+        let _lock = Lock::acquire(install).await?;
+        let file = Self::file_for(install);
+        if file.exists() {
+            return Err(format!(
+                "An unfinished transaction for {:?} exists at {:?}, run moon again to recover it",
+                install, file
+            )
+            .into());
+        }
+        fs::create_dir_all(Self::dir())?;
+        let mut journal = Self {
+            kind,
+            install: install.to_path_buf(),
+            operation,
+            entries: steps
+                .into_iter()
+                .map(|step| Entry {
+                    step,
+                    state: State::Pending,
+                })
+                .collect(),
+            file,
+        };
+        journal.save()?;
+
+        for i in 0..journal.entries.len() {
+            journal.entries[i].step.prepare();
+            journal.entries[i].state = State::Running;
+            journal.save()?;
+            if let Err(e) = journal.entries[i].step.apply() {
+                l::error!("{:?} failed at step {}: {}", journal.operation, i, e);
+                if let Err(re) = journal.rollback() {
+                    l::error!("Rollback failed, journal kept at {:?}: {}", journal.file, re);
+                }
+                return Err(e);
+            }
+            journal.entries[i].state = State::Done;
+            journal.save()?;
+        }
+
+        journal.commit()
+    }
+
+    fn rollback(&self) -> Result<(), Box<dyn Error>> {
+        l::warn!("Rolling back {:?} of {:?}", self.operation, self.install);
+        for entry in self.entries.iter().rev() {
+            if entry.state != State::Pending {
+                entry.step.undo()?;
+            }
+        }
+        fs::remove_file(&self.file)?;
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<(), Box<dyn Error>> {
+        for entry in &self.entries {
+            if let Err(e) = entry.step.finish() {
+                l::warn!("Failed to clean up after {:?}: {}", entry.step, e);
+            }
+        }
+        fs::remove_file(&self.file)?;
+        Ok(())
+    }
+
+    /// Looks for journals left behind by an interrupted moon run. Ones where
+    /// every step went through are committed, anything else is rolled back.
+    /// A journal that fails to recover is kept and doesn't stop the others.
+    pub async fn recover_all() -> Result<(), Box<dyn Error>> {
+        let dir = Self::dir();
+        if !dir.exists() {
+            return Ok(());
+        }
+        let mut failed = vec![];
+        for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+            let file = entry.path();
+            if file.extension().map(|e| e != "json").unwrap_or(true) {
+                continue;
+            }
+            if let Err(e) = Self::recover(file.clone()) {
+                l::error!("Failed to recover the journal at {:?}: {}", file, e);
+                failed.push(file);
+            }
+        }
+        if !failed.is_empty() {
+            return Err(
+                format!("{} journal(s) left unrecovered: {:?}", failed.len(), failed).into(),
+            );
+        }
+        Ok(())
+    }
+
+    fn recover(file: PathBuf) -> Result<(), Box<dyn Error>> {
+        let mut journal: Journal = match serde_json::from_str(&fs::read_to_string(&file)?) {
+            Ok(journal) => journal,
+            Err(e) => {
+                l::error!("Unreadable journal at {:?}: {}", file, e);
+                return Ok(());
+            }
+        };
+        // a journal whose install is locked belongs to a moon run that's
+        // still going, not an interrupted one
+        let _lock = match Lock::try_acquire(&journal.install)? {
+            Some(lock) => lock,
+            None => return Ok(()),
+        };
+        // it may have finished between reading the journal and locking
+        journal = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(_) => return Ok(()),
+        };
+        journal.file = file;
+        if journal.entries.iter().all(|e| e.state == State::Done) {
+            l::warn!(
+                "Finishing interrupted {:?} of {:?}",
+                journal.operation,
+                journal.install
+            );
+            journal.commit()
+        } else {
+            l::warn!(
+                "Undoing interrupted {:?} of {:?}",
+                journal.operation,
+                journal.install
+            );
+            journal.rollback()
+        }
+    }
+}
+
+#[inline(always)]
+fn remove_any(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() || path.is_symlink() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::test_dir;
+
+    /// An install with an app/ folder moon didn't make, holding a file.
+    fn install(name: &str) -> PathBuf {
+        let install = test_dir(name);
+        fs::create_dir_all(install.join("app")).unwrap();
+        fs::write(install.join("app/index.js"), "theirs").unwrap();
+        fs::write(install.join("app.asar"), "discord").unwrap();
+        install
+    }
+
+    fn steps(install: &Path) -> Vec<Step> {
+        let index = install.join("app/index.js");
+        vec![
+            Step::Rename {
+                from: install.join("app.asar"),
+                to: install.join("_app.asar"),
+            },
+            Step::CreateDir {
+                path: install.join("app"),
+                existed: false,
+            },
+            Step::CreateDir {
+                path: install.join("new"),
+                existed: false,
+            },
+            Step::Write {
+                backup: Some(Step::backup_for(&index)),
+                path: index,
+                contents: "ours".to_string(),
+            },
+            Step::Write {
+                path: install.join("app/injector.js"),
+                contents: "ours".to_string(),
+                backup: None,
+            },
+        ]
+    }
+
+    fn assert_untouched(install: &Path) {
+        assert_eq!(
+            fs::read_to_string(install.join("app.asar")).unwrap(),
+            "discord"
+        );
+        assert!(!install.join("_app.asar").exists());
+        assert_eq!(
+            fs::read_to_string(install.join("app/index.js")).unwrap(),
+            "theirs"
+        );
+        assert!(!install.join("app/injector.js").exists());
+        assert!(!install.join("app/index.js.moon-bak").exists());
+        assert!(!install.join("new").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_transaction_is_undone() {
+        let install = install("journal-undo");
+        let mut steps = steps(&install);
+        steps.push(Step::Rename {
+            from: install.join("missing"),
+            to: install.join("anywhere"),
+        });
+        let result =
+            Journal::transact(DiscordKind::Stable, &install, Operation::Inject, steps).await;
+        assert!(result.is_err());
+        assert_untouched(&install);
+        assert!(!Journal::file_for(&install).exists());
+    }
+
+    #[tokio::test]
+    async fn transaction_commits() {
+        let install = install("journal-commit");
+        Journal::transact(
+            DiscordKind::Stable,
+            &install,
+            Operation::Inject,
+            steps(&install),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(install.join("_app.asar")).unwrap(),
+            "discord"
+        );
+        assert_eq!(
+            fs::read_to_string(install.join("app/index.js")).unwrap(),
+            "ours"
+        );
+        assert!(install.join("app/injector.js").exists());
+        assert!(!install.join("app/index.js.moon-bak").exists());
+        assert!(!Journal::file_for(&install).exists());
+    }
+
+    #[tokio::test]
+    async fn replaced_file_is_restored() {
+        let install = install("journal-replace");
+        let asar = install.join("extensions/theirs.asar");
+        fs::create_dir_all(asar.parent().unwrap()).unwrap();
+        fs::write(&asar, "theirs").unwrap();
+        fs::write(install.join("ours.asar"), "ours").unwrap();
+        let steps = vec![
+            Step::Remove {
+                trash: Step::trash_for(&asar),
+                path: asar.clone(),
+            },
+            Step::Copy {
+                from: install.join("ours.asar"),
+                to: asar.clone(),
+                existed: false,
+            },
+            Step::Rename {
+                from: install.join("missing"),
+                to: install.join("anywhere"),
+            },
+        ];
+        let result =
+            Journal::transact(DiscordKind::Stable, &install, Operation::Inject, steps).await;
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&asar).unwrap(), "theirs");
+        assert_eq!(fs::read_dir(asar.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn copy_never_clobbers() {
+        let install = install("journal-copy-existing");
+        fs::write(install.join("ours.asar"), "ours").unwrap();
+        let steps = vec![Step::Copy {
+            from: install.join("ours.asar"),
+            to: install.join("app.asar"),
+            existed: false,
+        }];
+        let result =
+            Journal::transact(DiscordKind::Stable, &install, Operation::Inject, steps).await;
+        assert!(result.is_err());
+        assert_untouched(&install);
+        assert!(!Journal::file_for(&install).exists());
+    }
+
+    /// Runs `steps` up to `done` and leaves a journal behind, as a moon run
+    /// killed at that point would.
+    fn interrupt(install: &Path, done: usize) {
+        let mut entries = vec![];
+        for (i, mut step) in steps(install).into_iter().enumerate() {
+            let state = if i < done {
+                step.prepare();
+                step.apply().unwrap();
+                State::Done
+            } else {
+                State::Pending
+            };
+            entries.push(Entry { step, state });
+        }
+        let journal = Journal {
+            kind: DiscordKind::Stable,
+            install: install.to_path_buf(),
+            operation: Operation::Inject,
+            entries,
+            file: Journal::file_for(install),
+        };
+        fs::create_dir_all(Journal::dir()).unwrap();
+        journal.save().unwrap();
+    }
+
+    #[tokio::test]
+    async fn recover_undoes_interrupted() {
+        let install = install("journal-recover-undo");
+        interrupt(&install, 4);
+        Journal::recover_all().await.unwrap();
+        assert_untouched(&install);
+        assert!(!Journal::file_for(&install).exists());
+    }
+
+    #[tokio::test]
+    async fn recover_finishes_complete() {
+        let install = install("journal-recover-finish");
+        interrupt(&install, steps(&install).len());
+        Journal::recover_all().await.unwrap();
+        assert_eq!(
+            fs::read_to_string(install.join("app/index.js")).unwrap(),
+            "ours"
+        );
+        assert!(!install.join("app/index.js.moon-bak").exists());
+        assert!(!Journal::file_for(&install).exists());
+    }
+}
@@ -1,10 +1,31 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DiscordKind {
     Stable,
     Ptb,
     Canary,
     Development,
 }
+impl DiscordKind {
+    pub const ALL: [DiscordKind; 4] = [
+        DiscordKind::Stable,
+        DiscordKind::Ptb,
+        DiscordKind::Canary,
+        DiscordKind::Development,
+    ];
+
+    /// The name used for `--branch` on the command line.
+    #[inline(always)]
+    pub fn branch_name(&self) -> &'static str {
+        match self {
+            DiscordKind::Stable => "stable",
+            DiscordKind::Ptb => "ptb",
+            DiscordKind::Canary => "canary",
+            DiscordKind::Development => "development",
+        }
+    }
+}
 impl From<DiscordKind> for String {
     #[inline(always)]
     fn from(kind: DiscordKind) -> Self {
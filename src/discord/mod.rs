@@ -1,12 +1,23 @@
 mod kind;
 mod install;
 mod injected;
+mod backup;
+mod verify;
+mod foreign;
+mod journal;
+mod openasar;
 
 #[cfg(target_os = "linux")]
 mod flatpak;
 
 pub use kind::DiscordKind;
 pub use install::DiscordInstall;
+pub use journal::{Journal, Operation, Step};
+pub use verify::{Check, Repair};
+pub use foreign::ForeignPolicy;
+pub use injected::InjectorFormat;
+pub use backup::Backup;
+pub use openasar::{download_openasar, openasar_download};
 
 #[cfg(target_os = "linux")]
 pub use flatpak::Flatpak;
@@ -0,0 +1,142 @@
+use log as l;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::backup::Backup;
+use super::foreign::ForeignMod;
+use super::journal::Step;
+use super::DiscordInstall;
+use crate::asar::Asar;
+use crate::Platform;
+
+const OPENASAR_URL: &str =
+    "https://github.com/GooseMod/OpenAsar/releases/download/nightly/app.asar";
+
+/// Where the downloaded OpenAsar is kept until it's copied into an install.
+#[inline(always)]
+pub fn openasar_download() -> PathBuf {
+    PathBuf::from(Platform::data_dir())
+        .join("openasar")
+        .join("app.asar")
+}
+
+/// Fetches the latest OpenAsar release into `to`, making sure it really is
+/// OpenAsar before keeping it.
+pub async fn download_openasar(to: &Path) -> Result<(), Box<dyn Error>> {
+    l::info!("Downloading OpenAsar...");
+    let res = crate::http::get(OPENASAR_URL).await?;
+    let bytes = res.bytes().await?;
+    fs::create_dir_all(to.parent().unwrap())?;
+    let tmp = to.with_extension("asar.tmp");
+    fs::write(&tmp, &bytes)?;
+    if ForeignMod::identify_asar(&tmp) != Some(ForeignMod::OpenAsar) {
+        fs::remove_file(&tmp)?;
+        return Err(format!("{} didn't serve an OpenAsar archive", OPENASAR_URL).into());
+    }
+    fs::rename(tmp, to)?;
+    l::info!("Downloaded OpenAsar ({} bytes)", bytes.len());
+    Ok(())
+}
+
+impl DiscordInstall {
+    /// Where the asar Discord boots from lives: _app.asar once moonlight is
+    /// injected in front of it, app.asar otherwise.
+    #[inline(always)]
+    pub fn asar_slot(&self) -> PathBuf {
+        let resources = self.resources_dir();
+        let renamed = resources.join("_app.asar");
+        if self.injected || renamed.is_file() {
+            renamed
+        } else {
+            resources.join("app.asar")
+        }
+    }
+
+    /// Looks for OpenAsar in the asar slot, returning its version if found.
+    /// Discord's settings.json is only consulted when the asar can't be read.
+    pub(super) fn detect_openasar(&self) -> Option<String> {
+        let slot = self.asar_slot();
+        let settings = fs::read_to_string(self.user_data_dir().join("settings.json"))
+            .ok()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+            .map(|s| s.get("openasar").is_some())
+            .unwrap_or(false);
+        let archive = match Asar::open(&slot) {
+            Ok(archive) => archive,
+            Err(e) if settings => {
+                l::warn!("Can't read {:?} ({}), going by OpenAsar's settings", slot, e);
+                return Some("unknown".to_string());
+            }
+            Err(_) => return None,
+        };
+        if ForeignMod::identify_asar(&slot) != Some(ForeignMod::OpenAsar) {
+            if settings {
+                l::info!(
+                    "OpenAsar was set up for Discord {:?} before, but a Discord update replaced it",
+                    self.kind
+                );
+            }
+            return None;
+        }
+        Some(Self::openasar_version(&archive).unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    /// OpenAsar's release builds stamp `oaVersion` into index.js.
+    #[inline(always)]
+    fn openasar_version(archive: &Asar) -> Option<String> {
+        let index = String::from_utf8(archive.read("index.js").ok()?).ok()?;
+        let rest = &index[index.find("oaVersion")?..];
+        let quote = rest.find(['\'', '"'])?;
+        let rest = &rest[quote + 1..];
+        Some(rest[..rest.find(['\'', '"'])?].to_string())
+    }
+
+    /// The steps to put the OpenAsar at `openasar` in place of Discord's
+    /// asar. Any moonlight injection keeps loading whatever is in the slot,
+    /// so it carries on working on top.
+    pub fn plan_openasar_up(&self, openasar: &Path) -> Vec<Step> {
+        let slot = self.asar_slot();
+        let mut steps = vec![];
+        if slot.exists() {
+            steps.push(Step::Remove {
+                trash: Step::trash_for(&slot),
+                path: slot.clone(),
+            });
+        }
+        steps.push(Step::Copy {
+            from: openasar.to_path_buf(),
+            to: slot,
+            existed: false,
+        });
+        steps
+    }
+
+    /// The steps to put Discord's own asar back from its backup.
+    pub fn plan_openasar_down(&self) -> Result<Vec<Step>, Box<dyn Error>> {
+        let slot = self.asar_slot();
+        if ForeignMod::identify_asar(&slot) != Some(ForeignMod::OpenAsar) {
+            return Err(format!("OpenAsar isn't installed in Discord {:?}", self.kind).into());
+        }
+        let backup = match Backup::find(self) {
+            Some(backup) if backup.verify()? => backup,
+            Some(backup) => {
+                return Err(format!("Backup at {:?} failed its checksum", backup.asar).into())
+            }
+            None => return Err(format!("No backup found for {:?}", self.path).into()),
+        };
+        Ok(vec![
+            Step::Remove {
+                trash: Step::trash_for(&slot),
+                path: slot.clone(),
+            },
+            Step::Copy {
+                from: backup.asar,
+                to: slot,
+                existed: false,
+            },
+        ])
+    }
+}
@@ -0,0 +1,148 @@
+use log as l;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::backup::Backup;
+use super::injected::InjectorFormat;
+use super::install::PACKAGE_JSON;
+use super::journal::{Journal, Operation, Step};
+use super::DiscordInstall;
+use crate::asar::Asar;
+use crate::moonlight;
+
+/// What `moon verify --repair` can do about a failed check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Repair {
+    Asar,
+    InjectionFiles,
+    Dist,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    /// A short description of what was found either way.
+    pub result: Result<String, String>,
+    pub repair: Option<Repair>,
+}
+
+impl Check {
+    #[inline(always)]
+    fn new(name: &'static str, result: Result<String, String>, repair: Repair) -> Self {
+        Self {
+            name,
+            result,
+            repair: Some(repair),
+        }
+    }
+}
+
+impl DiscordInstall {
+    /// Whether there's an injection to verify: Discord's asar moved aside
+    /// with a lone _app.asar still counts, as a broken one.
+    #[inline(always)]
+    pub fn is_injected(&self) -> bool {
+        self.injected || self.resources_dir().join("_app.asar").exists()
+    }
+
+    /// Checks the injection end to end. `fallback_root` is used when
+    /// injector.js doesn't tell which moonlight root it loads. An install
+    /// that was never injected only gets a "not injected" check, which
+    /// nothing repairs.
+    pub fn verify(&self, fallback_root: &Path) -> (Vec<Check>, PathBuf) {
+        if !self.is_injected() {
+            let check = Check {
+                name: "injection",
+                result: Err("not injected, run moon up to inject it".to_string()),
+                repair: None,
+            };
+            return (vec![check], fallback_root.to_path_buf());
+        }
+        let resources = self.resources_dir();
+        let app = resources.join("app");
+        let mut checks = vec![];
+
+        checks.push(Check::new(
+            "package.json",
+            match fs::read_to_string(app.join("package.json")) {
+                Ok(package) if package == PACKAGE_JSON => Ok("matches".to_string()),
+                Ok(_) => Err("differs from what moon writes".to_string()),
+                Err(e) => Err(format!("unreadable: {}", e)),
+            },
+            Repair::InjectionFiles,
+        ));
+
+        let injector = self.injector();
+        let target = injector.as_ref().map(|i| i.target.clone());
+        checks.push(Check::new(
+            "injector.js",
+            match &injector {
+                Some(injector) if injector.format != InjectorFormat::Moon => Err(format!(
+                    "written by {:?}, run moon adopt to manage it",
+                    injector.format
+                )),
+                Some(injector) if injector.target.is_file() => {
+                    Ok(format!("loads {:?}", injector.target))
+                }
+                Some(injector) => Err(format!("loads {:?}, which doesn't exist", injector.target)),
+                None => Err("missing or not a moonlight loader".to_string()),
+            },
+            Repair::InjectionFiles,
+        ));
+
+        let asar = resources.join("_app.asar");
+        checks.push(Check::new(
+            "_app.asar",
+            match Asar::open(&asar) {
+                Ok(archive) if archive.get("package.json").is_some() => {
+                    Ok("present and valid".to_string())
+                }
+                Ok(_) => Err("has no package.json".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            Repair::Asar,
+        ));
+
+        let root = target
+            .as_ref()
+            .and_then(|t| Some(t.parent()?.parent()?.to_path_buf()))
+            .unwrap_or_else(|| fallback_root.to_path_buf());
+        checks.push(Check::new(
+            "dist",
+            moonlight::verify_dist(&root)
+                .map(|(channel, version)| format!("{:?} {} at {:?}", channel, version, root)),
+            Repair::Dist,
+        ));
+
+        (checks, root)
+    }
+
+    /// Puts the backed up original asar back at resources/_app.asar.
+    pub async fn repair_asar(&self) -> Result<(), Box<dyn Error>> {
+        let backup = match Backup::find(self) {
+            Some(backup) if backup.verify()? => backup,
+            Some(backup) => {
+                return Err(format!("Backup at {:?} failed its checksum", backup.asar).into())
+            }
+            None => return Err(format!("No backup found for {:?}", self.path).into()),
+        };
+        let asar = self.resources_dir().join("_app.asar");
+        let mut steps = vec![];
+        if asar.exists() {
+            steps.push(Step::Remove {
+                trash: Step::trash_for(&asar),
+                path: asar.clone(),
+            });
+        }
+        steps.push(Step::Copy {
+            from: backup.asar,
+            to: asar,
+            existed: false,
+        });
+        l::info!("Restoring _app.asar from backup of {}", backup.host_version);
+        Journal::run(self.kind, &self.path, Operation::Repair, steps).await
+    }
+}
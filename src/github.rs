@@ -0,0 +1,199 @@
+use log as l;
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    error::Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::{self, Cache, LATEST_TTL};
+use crate::state::format_time;
+
+/// A rate limit resetting sooner than this is waited out, later ones fail.
+const MAX_WAIT: u64 = 60;
+/// Warn once fewer requests than this are left.
+const LOW_REMAINING: u64 = 5;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+    /// `sha256:<hex>`, on assets GitHub has hashed.
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+/// GitHub's rate limit is used up, with no cached answer to fall back on.
+#[derive(Debug)]
+pub struct RateLimited(String);
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for RateLimited {}
+
+/// What GitHub's rate limit headers say.
+#[derive(Debug, Default)]
+struct RateLimit {
+    remaining: Option<u64>,
+    /// Seconds since the unix epoch.
+    reset: Option<u64>,
+    retry_after: Option<u64>,
+}
+
+impl RateLimit {
+    #[inline(always)]
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let number = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+        Self {
+            remaining: number("x-ratelimit-remaining"),
+            reset: number("x-ratelimit-reset"),
+            retry_after: number("retry-after"),
+        }
+    }
+
+    /// A 403 is only the rate limit when GitHub says so, otherwise it's
+    /// e.g. a bad token.
+    #[inline(always)]
+    fn hit(&self, status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN
+                && (self.remaining == Some(0) || self.retry_after.is_some()))
+    }
+
+    /// Seconds until requests are allowed again.
+    #[inline(always)]
+    fn wait(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.retry_after
+            .or(self.reset.map(|reset| reset.saturating_sub(now)))
+            .unwrap_or(MAX_WAIT + 1)
+    }
+}
+
+/// GETs `path` under the configured repo from the configured GitHub API,
+/// authenticated if a token is set. Answers are cached: reused as is within
+/// `ttl`, otherwise revalidated by their ETag, which costs no rate limit.
+/// A short rate limit is waited out, a long one falls back to the cache.
+/// `None` if GitHub has nothing at `path`.
+async fn get<T: DeserializeOwned>(
+    path: &str,
+    ttl: Option<Duration>,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let network = http::network()?;
+    let token = network.github_token();
+    let url = format!("{}/repos/{}/{}", network.github_api(), network.repo(), path);
+    let mut cache = Cache::open(&url);
+    if let Some(body) = ttl.and_then(|ttl| cache.fresh(ttl)) {
+        l::debug!("Using the cached {}", url);
+        return Ok(Some(serde_json::from_str(body)?));
+    }
+    let mut waited = false;
+    let mut retried = false;
+    loop {
+        let mut req = cache.revalidate(
+            http::client()?
+                .get(&url)
+                .header("Accept", "application/vnd.github+json"),
+        );
+        if let Some(token) = &token {
+            req = req.bearer_auth(token);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        let limit = RateLimit::from_headers(res.headers());
+
+        if status == StatusCode::NOT_MODIFIED && cache.body().is_none() && !retried {
+            l::debug!("{} answered 304 with nothing cached, asking again", url);
+            retried = true;
+            continue;
+        }
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            if let Some(remaining) = limit.remaining.filter(|r| *r < LOW_REMAINING) {
+                l::warn!("Only {} GitHub API requests left for now", remaining);
+            }
+            return Ok(Some(serde_json::from_str(&cache.store(&url, res).await?)?));
+        }
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if status == StatusCode::UNAUTHORIZED && token.is_some() {
+            return Err("GitHub refused the configured token, check it or remove it".into());
+        }
+        if !limit.hit(status) {
+            return Err(format!("{} answered {}", url, status).into());
+        }
+
+        let wait = limit.wait();
+        if !waited && wait <= MAX_WAIT {
+            l::warn!("GitHub's rate limit was hit, waiting {}s", wait);
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            waited = true;
+            continue;
+        }
+        let until = limit
+            .reset
+            .map(|reset| format!(" until {} UTC", format_time(reset)))
+            .unwrap_or_default();
+        if let Some(cached) = cache.body() {
+            l::warn!(
+                "GitHub's rate limit is used up{}, using the release info cached from the last run",
+                until
+            );
+            return Ok(Some(serde_json::from_str(cached)?));
+        }
+        let hint = match token {
+            Some(_) => "",
+            None => ", set MOON_GITHUB_TOKEN or moon config --github-token for a higher limit",
+        };
+        return Err(RateLimited(format!("GitHub's rate limit is used up{}{}", until, hint)).into());
+    }
+}
+
+#[inline(always)]
+pub async fn latest_release() -> Result<Release, Box<dyn Error>> {
+    get("releases/latest", Some(LATEST_TTL))
+        .await?
+        .ok_or_else(|| "The repo has no releases".into())
+}
+
+/// Asks for the latest release again, for `moon doctor` to tell GitHub is
+/// reachable. Revalidating a cached one costs no rate limit.
+#[inline(always)]
+pub async fn check() -> Result<(), Box<dyn Error>> {
+    get::<Release>("releases/latest", None)
+        .await?
+        .map(|_| ())
+        .ok_or_else(|| "The repo has no releases".into())
+}
+
+/// `None` if there's no release tagged `tag`.
+#[inline(always)]
+pub async fn release_by_tag(tag: &str) -> Result<Option<Release>, Box<dyn Error>> {
+    get(&format!("releases/tags/{}", tag), None).await
+}
+
+/// The 100 most recent releases.
+#[inline(always)]
+pub async fn releases() -> Result<Vec<Release>, Box<dyn Error>> {
+    get("releases?per_page=100", Some(LATEST_TTL))
+        .await?
+        .ok_or_else(|| "The repo doesn't exist".into())
+}
@@ -0,0 +1,219 @@
+use log as l;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, RequestBuilder, Response, StatusCode};
+
+use crate::config::{Config, Network};
+use crate::platform::sha256_bytes;
+use crate::Platform;
+
+/// How long a lookup of what's newest is trusted before asking again.
+pub const LATEST_TTL: Duration = Duration::from_secs(5 * 60);
+
+static HTTP: OnceLock<(Network, Client)> = OnceLock::new();
+
+/// A client builder honouring `network`'s proxy and extra CAs.
+pub fn builder(network: &Network) -> Result<ClientBuilder, Box<dyn Error>> {
+    let mut builder = Client::builder().user_agent(concat!("moon/", env!("CARGO_PKG_VERSION")));
+    if let Some(proxy) = &network.proxy {
+        builder = builder
+            .proxy(Proxy::all(proxy).map_err(|e| format!("Invalid proxy {}: {}", proxy, e))?);
+    }
+    for ca in &network.ca_certs {
+        let pem = fs::read(ca).map_err(|e| format!("Can't read CA certificate {:?}: {}", ca, e))?;
+        builder = builder.add_root_certificate(
+            Certificate::from_pem(&pem)
+                .map_err(|e| format!("Invalid CA certificate {:?}: {}", ca, e))?,
+        );
+    }
+    Ok(builder)
+}
+
+/// Where the system keeps its CA bundle on the usual distros, as OpenSSL
+/// would look for it.
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/pki/tls/cacert.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// A PEM file holding the system's CA bundle followed by `network`'s extra
+/// certificates, for clients that only take one CA file. `None` without
+/// extra certificates, the system's are used as is then.
+pub fn ca_bundle(network: &Network) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if network.ca_certs.is_empty() {
+        return Ok(None);
+    }
+    let system = std::env::var_os("SSL_CERT_FILE")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(SYSTEM_CA_BUNDLES.iter().map(PathBuf::from))
+        .find(|bundle| bundle.is_file());
+    let mut pem = match &system {
+        Some(system) => fs::read(system)?,
+        None => {
+            l::warn!("Found no system CA bundle, only trusting the configured certificates");
+            vec![]
+        }
+    };
+    for ca in &network.ca_certs {
+        pem.push(b'\n');
+        pem.extend(fs::read(ca).map_err(|e| format!("Can't read CA certificate {:?}: {}", ca, e))?);
+    }
+    let bundle = PathBuf::from(Platform::data_dir()).join("ca-bundle.pem");
+    fs::create_dir_all(bundle.parent().unwrap())?;
+    fs::write(&bundle, pem)?;
+    Ok(Some(bundle))
+}
+
+/// The network settings from the config, and the client built from them,
+/// both loaded once per run.
+fn http() -> Result<&'static (Network, Client), Box<dyn Error>> {
+    if let Some(http) = HTTP.get() {
+        return Ok(http);
+    }
+    let network = Config::load()?.network;
+    let client = builder(&network)?.build()?;
+    Ok(HTTP.get_or_init(|| (network, client)))
+}
+
+#[inline(always)]
+pub fn network() -> Result<&'static Network, Box<dyn Error>> {
+    Ok(&http()?.0)
+}
+
+#[inline(always)]
+pub fn client() -> Result<&'static Client, Box<dyn Error>> {
+    Ok(&http()?.1)
+}
+
+/// GETs `url` through the configured client, failing on an error status.
+pub async fn get(url: &str) -> Result<Response, Box<dyn Error>> {
+    Ok(client()?.get(url).send().await?.error_for_status()?)
+}
+
+#[inline(always)]
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    etag: Option<String>,
+    /// When the body was last fetched or revalidated, in seconds since the
+    /// unix epoch.
+    checked: u64,
+    body: String,
+}
+
+/// The last response for a URL, kept under moon's data dir so it can be
+/// revalidated by its ETag, reused within a TTL or fallen back on.
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entry: Option<Entry>,
+}
+
+impl Cache {
+    pub fn open(url: &str) -> Self {
+        // named by the url's hash, any url makes a valid file name that way
+        let path = PathBuf::from(Platform::data_dir())
+            .join("cache")
+            .join("http")
+            .join(format!("{}.json", &sha256_bytes(url.as_bytes())[..32]));
+        let entry = fs::read_to_string(&path)
+            .ok()
+            .and_then(|e| serde_json::from_str::<Entry>(&e).ok())
+            .filter(|e| e.url == url);
+        Self { path, entry }
+    }
+
+    /// The cached body, if it was checked less than `ttl` ago.
+    #[inline(always)]
+    pub fn fresh(&self, ttl: Duration) -> Option<&str> {
+        self.entry
+            .as_ref()
+            .filter(|e| now().saturating_sub(e.checked) < ttl.as_secs())
+            .map(|e| e.body.as_str())
+    }
+
+    /// The cached body, however old.
+    #[inline(always)]
+    pub fn body(&self) -> Option<&str> {
+        self.entry.as_ref().map(|e| e.body.as_str())
+    }
+
+    /// Makes `req` conditional on the cached body having changed.
+    #[inline(always)]
+    pub fn revalidate(&self, req: RequestBuilder) -> RequestBuilder {
+        match self.entry.as_ref().and_then(|e| e.etag.as_ref()) {
+            Some(etag) => req.header("If-None-Match", etag),
+            None => req,
+        }
+    }
+
+    /// Keeps `res`'s body for next time and returns it. A 304 keeps and
+    /// returns the cached one instead.
+    pub async fn store(&mut self, url: &str, res: Response) -> Result<String, Box<dyn Error>> {
+        let entry = match (res.status(), self.entry.take()) {
+            (StatusCode::NOT_MODIFIED, Some(entry)) => Entry {
+                checked: now(),
+                ..entry
+            },
+            // error_for_status lets a 304 through, and it has no body to keep
+            (StatusCode::NOT_MODIFIED, None) => {
+                return Err(format!("{} answered 304 with nothing cached", url).into())
+            }
+            _ => {
+                let res = res.error_for_status()?;
+                Entry {
+                    url: url.to_string(),
+                    etag: res
+                        .headers()
+                        .get("etag")
+                        .and_then(|e| e.to_str().ok())
+                        .map(|e| e.to_string()),
+                    checked: now(),
+                    body: res.text().await?,
+                }
+            }
+        };
+        let body = entry.body.clone();
+        if let Err(e) = fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| fs::write(&self.path, serde_json::to_vec(&entry)?))
+        {
+            l::debug!("Failed to cache {}: {}", url, e);
+        }
+        self.entry = Some(entry);
+        Ok(body)
+    }
+}
+
+/// GETs `url` as text through the cache: within `ttl` of the last check the
+/// cached body is used as is, otherwise it's revalidated by its ETag.
+pub async fn get_cached(url: &str, ttl: Option<Duration>) -> Result<String, Box<dyn Error>> {
+    let mut cache = Cache::open(url);
+    if let Some(body) = ttl.and_then(|ttl| cache.fresh(ttl)) {
+        l::debug!("Using the cached {}", url);
+        return Ok(body.to_string());
+    }
+    let mut res = cache.revalidate(client()?.get(url)).send().await?;
+    if res.status() == StatusCode::NOT_MODIFIED && cache.body().is_none() {
+        l::debug!("{} answered 304 with nothing cached, asking again", url);
+        res = client()?.get(url).send().await?;
+    }
+    cache.store(url, res).await
+}
@@ -0,0 +1,128 @@
+use fs2::FileExt;
+use log as l;
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::Platform;
+
+static WAIT: AtomicBool = AtomicBool::new(true);
+/// Lock files this run holds. Taking one of them again is a no-op rather
+/// than a deadlock, so a command can hold an install's lock from planning
+/// through to the journal that runs the plan.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// Whether to wait for, rather than fail on, a lock another moon run holds.
+#[inline(always)]
+pub fn set_wait(wait: bool) {
+    WAIT.store(wait, Ordering::Relaxed);
+}
+
+/// An advisory lock on a moonlight root or a Discord install, held until
+/// dropped, so two moon runs never touch the same files at once.
+#[derive(Debug)]
+pub struct Lock {
+    /// `None` for a lock this run already held when it was taken.
+    file: Option<(File, PathBuf)>,
+}
+
+impl Lock {
+    #[inline(always)]
+    pub fn dir() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("locks")
+    }
+
+    /// Lock files live in moon's data dir rather than next to what they
+    /// lock, Discord's folders and the root shouldn't collect moon's litter.
+    #[inline(always)]
+    fn file_for(target: &Path) -> PathBuf {
+        let target = fs::canonicalize(target).unwrap_or_else(|_| target.to_path_buf());
+        let name = target
+            .to_string_lossy()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        Self::dir().join(format!("{}.lock", name))
+    }
+
+    #[inline(always)]
+    fn open(path: &Path) -> Result<File, Box<dyn Error>> {
+        fs::create_dir_all(Self::dir())?;
+        Ok(OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?)
+    }
+
+    #[inline(always)]
+    fn held(path: &Path) -> bool {
+        HELD.lock().unwrap().iter().any(|held| held == path)
+    }
+
+    #[inline(always)]
+    fn hold(file: File, path: PathBuf) -> Self {
+        HELD.lock().unwrap().push(path.clone());
+        Self {
+            file: Some((file, path)),
+        }
+    }
+
+    /// Takes the lock only if nobody else holds it, this run included.
+    pub fn try_acquire(target: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = Self::file_for(target);
+        if Self::held(&path) {
+            return Ok(None);
+        }
+        let file = Self::open(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(_) => Ok(Some(Self::hold(file, path))),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Takes the lock, waiting for whoever holds it unless `--no-wait` was given.
+    /// Already held by this run, it's handed out again and stays locked
+    /// until the first one is dropped.
+    pub async fn acquire(target: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = Self::file_for(target);
+        if Self::held(&path) {
+            return Ok(Self { file: None });
+        }
+        let file = Self::open(&path)?;
+        match file.try_lock_exclusive() {
+            Ok(_) => {}
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                if !WAIT.load(Ordering::Relaxed) {
+                    return Err(format!(
+                        "{:?} is in use by another moon run, try again once it's done",
+                        target
+                    )
+                    .into());
+                }
+                l::warn!("Waiting for another moon run to finish with {:?}...", target);
+                let waiting = file.try_clone()?;
+                tokio::task::spawn_blocking(move || waiting.lock_exclusive()).await??;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        l::debug!("Locked {:?}", target);
+        Ok(Self::hold(file, path))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some((file, path)) = &self.file {
+            let _ = file.unlock();
+            HELD.lock().unwrap().retain(|held| held != path);
+        }
+    }
+}
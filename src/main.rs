@@ -1,9 +1,15 @@
-#![feature(async_closure)]
-
+mod asar;
+mod bundle;
 mod cli;
+mod config;
 mod discord;
+mod github;
+mod http;
+mod lock;
 mod moonlight;
+mod plan;
 mod platform;
+mod state;
 
 use clap::Parser;
 use env_logger::Env;
@@ -20,6 +26,12 @@ async fn main() {
     let args = cli::Args::parse();
 
     l::info!("Moon v{}", env!("CARGO_PKG_VERSION"));
+    lock::set_wait(!args.no_wait);
+    // an install whose journal is left over refuses new transactions anyway,
+    // so everything else, restore and doctor included, still gets to run
+    if let Err(e) = discord::Journal::recover_all().await {
+        l::error!("Failed to recover an interrupted transaction: {}", e);
+    }
     match args.subcommand {
         Subcommand::Up(up_args) => {
             actions::up(up_args).await;
@@ -33,6 +45,39 @@ async fn main() {
         Subcommand::Openasar(openasar_args) => {
             actions::openasar(openasar_args).await;
         }
+        Subcommand::Restore(restore_args) => {
+            actions::restore(restore_args).await;
+        }
+        Subcommand::Verify(verify_args) => {
+            actions::verify(verify_args).await;
+        }
+        Subcommand::Doctor(doctor_args) => {
+            actions::doctor(doctor_args).await;
+        }
+        Subcommand::Adopt(adopt_args) => {
+            actions::adopt(adopt_args).await;
+        }
+        Subcommand::Relocate(relocate_args) => {
+            actions::relocate(relocate_args).await;
+        }
+        Subcommand::Config(config_args) => {
+            actions::config(config_args).await;
+        }
+        Subcommand::History(history_args) => {
+            actions::history(history_args).await;
+        }
+        Subcommand::Fetch(fetch_args) => {
+            actions::fetch(fetch_args).await;
+        }
+        Subcommand::Rollback(rollback_args) => {
+            actions::rollback(rollback_args).await;
+        }
+        Subcommand::Purge(purge_args) => {
+            actions::purge(purge_args).await;
+        }
+        Subcommand::Asar(asar_args) => {
+            actions::asar(asar_args).await;
+        }
     }
 
     l::info!("Finished in {}ms", stopwatch.elapsed().as_millis());
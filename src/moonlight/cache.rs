@@ -0,0 +1,81 @@
+use log as l;
+use std::{error::Error, fs, path::PathBuf};
+
+use crate::platform::sha256_bytes;
+use crate::Platform;
+
+/// Tarballs kept at most, the oldest go first.
+const KEEP_DISTS: usize = 10;
+
+/// Verified dist tarballs, each stored once as `<sha256>.tar.gz` and found by
+/// the versions it was installed as through `<version id>.sha256`.
+#[inline(always)]
+fn dists_dir() -> PathBuf {
+    PathBuf::from(Platform::data_dir())
+        .join("cache")
+        .join("dists")
+}
+
+/// Whether a tarball for version `id` is cached, without reading it.
+#[inline(always)]
+pub fn is_cached(id: &str) -> bool {
+    let dir = dists_dir();
+    fs::read_to_string(dir.join(format!("{}.sha256", id)))
+        .map(|sha256| dir.join(format!("{}.tar.gz", sha256.trim())).is_file())
+        .unwrap_or(false)
+}
+
+/// The tarball cached for version `id` and its SHA-256, if there is one and
+/// it still hashes to what it was stored as.
+pub fn cached_dist(id: &str) -> Option<(Vec<u8>, String)> {
+    let dir = dists_dir();
+    let sha256 = fs::read_to_string(dir.join(format!("{}.sha256", id))).ok()?;
+    let sha256 = sha256.trim();
+    let tarball = fs::read(dir.join(format!("{}.tar.gz", sha256))).ok()?;
+    if sha256_bytes(&tarball) != sha256 {
+        l::warn!(
+            "The cached dist for {} is corrupt, downloading it again",
+            id
+        );
+        let _ = fs::remove_file(dir.join(format!("{}.tar.gz", sha256)));
+        return None;
+    }
+    l::debug!("Using the cached dist for {}", id);
+    Some((tarball, sha256.to_string()))
+}
+
+/// Keeps a verified `tarball` for version `id`, dropping the oldest past
+/// `KEEP_DISTS`.
+pub fn cache_dist(id: &str, tarball: &[u8], sha256: &str) -> Result<(), Box<dyn Error>> {
+    let dir = dists_dir();
+    fs::create_dir_all(&dir)?;
+    let file = dir.join(format!("{}.tar.gz", sha256));
+    if !file.exists() {
+        let tmp = dir.join(format!("{}.tar.gz.tmp", sha256));
+        fs::write(&tmp, tarball)?;
+        fs::rename(tmp, &file)?;
+    }
+    fs::write(dir.join(format!("{}.sha256", id)), sha256)?;
+
+    let mut tarballs = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".tar.gz"))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect::<Vec<_>>();
+    tarballs.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, old) in tarballs.into_iter().skip(KEEP_DISTS) {
+        fs::remove_file(old)?;
+    }
+    // ids pointing at a tarball that's gone are dropped with it
+    for entry in fs::read_dir(&dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "sha256")
+            && !fs::read_to_string(&path)
+                .map(|sha256| dir.join(format!("{}.tar.gz", sha256.trim())).exists())
+                .unwrap_or(false)
+        {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
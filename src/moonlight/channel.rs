@@ -1,9 +1,14 @@
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Channel {
     Stable,
     Nightly,
-    Git 
+    Git,
+    /// A dist installed from disk with `moon up --from`, it can't be fetched again.
+    Local,
 }
 
 impl From<String> for Channel {
@@ -13,7 +18,32 @@ impl From<String> for Channel {
             "stable" => Channel::Stable,
             "nightly" => Channel::Nightly,
             "git" => Channel::Git,
+            "local" => Channel::Local,
             _ => panic!("Invalid channel: {}", channel),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Channel {
+    /// The name written to branch.txt, the inverse of `parse`.
+    #[inline(always)]
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Nightly => "nightly",
+            Channel::Git => "git",
+            Channel::Local => "local",
+        }
+    }
+
+    #[inline(always)]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "stable" => Some(Channel::Stable),
+            "nightly" => Some(Channel::Nightly),
+            "git" => Some(Channel::Git),
+            "local" => Some(Channel::Local),
+            _ => None,
+        }
+    }
+}
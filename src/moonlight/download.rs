@@ -1,98 +1,336 @@
 use log as l;
-use octocrab::models::repos::Object;
-use octocrab::params::repos::Reference;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-use crate::platform::env;
+use crate::config::{Config, DEFAULT_KEEP_VERSIONS};
+use crate::github::{self, Asset, Release};
+use crate::http;
+use crate::lock::Lock;
+use crate::platform::{copy_dir, sha256_bytes};
 use crate::Platform;
 
-use super::Channel;
+use super::cache::{cache_dist, cached_dist, is_cached};
+use super::versions::{
+    activate, active_version, clear_staging, migrate_legacy, prune, version_id, versions_dir,
+    Staged,
+};
+use super::{verify_dist, Channel, DIST_FILES};
 
+/// The stable release `version` names, either a tag or a semver range
+/// resolved against the release list. The latest release without one.
+async fn stable_release(version: Option<&str>) -> Result<Release, Box<dyn std::error::Error>> {
+    let spec = match version {
+        Some(spec) => spec,
+        None => return github::latest_release().await,
+    };
+    for tag in pinned_tags(spec) {
+        if let Some(release) = github::release_by_tag(&tag).await? {
+            return Ok(release);
+        }
+    }
+    let req = semver::VersionReq::parse(spec)
+        .map_err(|_| format!("No release tagged {} and it isn't a semver range", spec))?;
+    github::releases()
+        .await?
+        .into_iter()
+        .filter(|r| !r.draft && !r.prerelease)
+        .filter_map(|r| Some((semver::Version::parse(r.tag_name.trim_start_matches('v')).ok()?, r)))
+        .filter(|(v, _)| req.matches(v))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, r)| r)
+        .ok_or_else(|| format!("No release matches {}", spec).into())
+}
+
+/// The SHA-256 in a checksum file for `file`: either a `sha256sum` style
+/// listing, or a file holding nothing but the digest.
+pub(super) fn parse_checksum(text: &str, file: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let hash = parts.next()?;
+        let name = parts.next().map(|name| name.trim_start_matches('*'));
+        let valid = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+        (valid && name.map(|name| name == file).unwrap_or(true)).then(|| hash.to_lowercase())
+    })
+}
+
+/// The SHA-256 published for a release asset: GitHub's own digest for it,
+/// or else a checksums file released alongside it.
+async fn release_digest(
+    release: &Release,
+    asset: &Asset,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(digest) = asset
+        .digest
+        .as_deref()
+        .and_then(|d| d.strip_prefix("sha256:"))
+    {
+        return Ok(digest.to_lowercase());
+    }
+    for sums in release.assets.iter().filter(|a| {
+        let name = a.name.to_lowercase();
+        name == format!("{}.sha256", asset.name)
+            || name.contains("sha256sums")
+            || name.contains("checksums")
+    }) {
+        let text = http::get_cached(&sums.browser_download_url, None).await?;
+        if let Some(hash) = parse_checksum(&text, &asset.name) {
+            return Ok(hash);
+        }
+    }
+    Err(format!(
+        "Release {} publishes no SHA-256 for {}, refusing to install it unverified",
+        release.tag_name, asset.name
+    )
+    .into())
+}
+
+/// Checks `tarball` hashes to `expected`, returning the hash.
+#[inline(always)]
+pub(super) fn check_digest(
+    tarball: &[u8],
+    expected: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let actual = sha256_bytes(tarball);
+    if actual != expected {
+        return Err(format!(
+            "The downloaded dist's SHA-256 is {}, but {} was published, refusing to install it",
+            actual, expected
+        )
+        .into());
+    }
+    l::debug!("Verified dist SHA-256 {}", actual);
+    Ok(actual)
+}
+
+/// A stable or nightly dist tarball as published, not downloaded yet.
+pub struct Published {
+    pub channel: Channel,
+    pub version: String,
+    /// The stable release it's an asset of. Nightly has none, nor does a
+    /// pinned stable version found in the cache without asking GitHub.
+    release: Option<Release>,
+}
+
+impl Published {
+    /// The tarball `channel` serves now, or the stable release `version` names.
+    pub async fn resolve(
+        channel: Channel,
+        version: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match channel {
+            Channel::Stable => {
+                if let Some(tag) = version.and_then(cached_tag) {
+                    return Ok(Self {
+                        channel,
+                        version: tag,
+                        release: None,
+                    });
+                }
+                let release = stable_release(version).await?;
+                Ok(Self {
+                    channel,
+                    version: release.tag_name.clone(),
+                    release: Some(release),
+                })
+            }
+            Channel::Nightly => Ok(Self {
+                channel,
+                // revalidated every time rather than trusted for a while, so it
+                // names the nightly whose checksum and tarball come right after
+                version: http::get_cached(&format!("{}/ref", http::network()?.nightly_url()), None)
+                    .await?
+                    .split("\n")
+                    .next()
+                    .unwrap()
+                    .to_owned(),
+                release: None,
+            }),
+            _ => Err(format!("moonlight doesn't publish {:?} tarballs", channel).into()),
+        }
+    }
+
+    /// Downloads the tarball and checks it against its published SHA-256,
+    /// returning it along with the hash. A tarball verified before is taken
+    /// from the cache instead.
+    pub async fn fetch(&self) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
+        let id = version_id(self.channel, &self.version);
+        if let Some(cached) = cached_dist(&id) {
+            return Ok(cached);
+        }
+        let (url, digest) = match &self.release {
+            Some(release) => {
+                let asset = release
+                    .assets
+                    .iter()
+                    .find(|a| a.name == "dist.tar.gz")
+                    .ok_or_else(|| format!("Release {} has no dist.tar.gz", self.version))?;
+                (
+                    asset.browser_download_url.clone(),
+                    release_digest(release, asset).await?,
+                )
+            }
+            None if self.channel == Channel::Stable => {
+                return Err(format!("The cached dist for {} is gone", self.version).into())
+            }
+            None => {
+                let nightly = http::network()?.nightly_url();
+                let checksum = http::get_cached(&format!("{}/dist.tar.gz.sha256", nightly), None)
+                    .await
+                    .map_err(|e| {
+                        format!(
+                            "Nightly publishes no SHA-256 for its dist ({}), refusing to install it unverified",
+                            e
+                        )
+                    })?;
+                (
+                    format!("{}/dist.tar.gz", nightly),
+                    parse_checksum(&checksum, "dist.tar.gz")
+                        .ok_or("Nightly's dist.tar.gz.sha256 holds no SHA-256")?,
+                )
+            }
+        };
+        let tarball = http::get(&url).await?.bytes().await?;
+        let sha256 = check_digest(&tarball, &digest)?;
+        if let Err(e) = cache_dist(&id, &tarball, &sha256) {
+            l::warn!("Failed to cache the downloaded dist: {}", e);
+        }
+        Ok((tarball.to_vec(), sha256))
+    }
+}
+
+/// The tags a pinned stable `spec` could be, if it isn't a range.
+#[inline(always)]
+fn pinned_tags(spec: &str) -> [String; 2] {
+    [spec.to_string(), format!("v{}", spec)]
+}
+
+/// The tag a pinned stable `spec` names, if its tarball is cached.
+#[inline(always)]
+fn cached_tag(spec: &str) -> Option<String> {
+    pinned_tags(spec)
+        .into_iter()
+        .find(|tag| is_cached(&version_id(Channel::Stable, tag)))
+}
+
+/// Activates `id` if it's already kept under `path`, so going back to a
+/// version downloaded before doesn't fetch it again.
+fn reuse(path: &Path, id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if active_version(path).as_deref() == Some(id) && verify_dist(path).is_ok() {
+        l::info!("Moonlight is up to date");
+        return Ok(true);
+    }
+    let dir = versions_dir(path).join(id);
+    if !dir.join("version.txt").is_file() || !DIST_FILES.iter().all(|f| dir.join(f).is_file()) {
+        return Ok(false);
+    }
+    l::info!("Moonlight {} was downloaded before, switching back to it", id);
+    activate(path, id)?;
+    Ok(true)
+}
+
+/// Only plain relative paths stay inside the folder they're unpacked in.
+#[inline(always)]
+fn contained(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Unpacks a dist tarball into a staged version `id` under `path`. Entries
+/// or links that would reach outside of it fail the whole unpack.
+pub(super) fn unpack(
+    path: &Path,
+    id: &str,
+    tarball: &[u8],
+) -> Result<Staged, Box<dyn std::error::Error>> {
+    let staged = Staged::new(path, id)?;
+    let mut archive =
+        tar::Archive::new(flate2::read::GzDecoder::new(std::io::Cursor::new(tarball)));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_path_buf();
+        if !contained(&name) {
+            return Err(format!("The dist tarball has an entry outside of it: {:?}", name).into());
+        }
+        if let Some(target) = entry.link_name()? {
+            // symlink targets are relative to the link, hard links to the archive
+            let base = match entry.header().entry_type() {
+                tar::EntryType::Symlink => name.parent().unwrap_or(Path::new("")).to_path_buf(),
+                _ => PathBuf::new(),
+            };
+            let mut resolved = vec![];
+            for c in base.join(&target).components() {
+                match c {
+                    Component::Normal(part) => resolved.push(part.to_owned()),
+                    Component::CurDir => {}
+                    Component::ParentDir if resolved.pop().is_some() => {}
+                    _ => {
+                        return Err(format!(
+                            "The dist tarball links {:?} outside of it, to {:?}",
+                            name, target
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
+        entry.unpack_in(staged.dir())?;
+    }
+    Ok(staged)
+}
+
+/// Drops the versions past the configured retention.
+pub(super) fn prune_versions(path: &Path) {
+    let keep = Config::load()
+        .map(|config| config.keep_versions())
+        .unwrap_or(DEFAULT_KEEP_VERSIONS);
+    if let Err(e) = prune(path, keep) {
+        l::warn!("Failed to remove old moonlight versions from {:?}: {}", path, e);
+    }
+}
+
+/// `version` pins the stable channel to a release, see `stable_release`.
+/// Each version is kept in its own folder under `<path>/versions`, and
+/// dist/injector.js loads whichever is active. A new version is staged and
+/// checked first, so a failed download leaves the active one untouched.
 pub async fn download(
     channel: Channel,
     path: PathBuf,
     repo_location: Option<String>,
+    version: Option<String>,
     check_ver: bool,
 ) -> Result<bool, Box<dyn std::error::Error>> {
     // returns <needs_build, err>
 
+    if version.is_some() && channel != Channel::Stable {
+        return Err(format!("Only stable releases can be pinned, not {:?}", channel).into());
+    }
+
+    // held until the dist is written, so two runs never unpack over each other
+    let _lock = Lock::acquire(&path).await?;
     let stopwatch = std::time::Instant::now();
 
-    if !path.join("dist").exists() {
-        fs::create_dir_all(&path.join("dist"))?;
-    }
+    fs::create_dir_all(&path)?;
+    migrate_legacy(&path)?;
+    clear_staging(&path)?;
     match channel {
-        Channel::Stable => {
-            let release = octocrab::instance()
-                .repos("moonlight-mod", "moonlight")
-                .releases()
-                .get_latest()
-                .await?;
-
-            if check_ver {
-                if path.join("dist/version.txt").exists() {
-                    if fs::read_to_string(path.join("dist/version.txt"))?
-                        .contains(release.tag_name.as_str())
-                    {
-                        l::info!("Moonlight is up to date");
+        Channel::Stable | Channel::Nightly => {
+            // a pinned version kept from before needs no lookup at all
+            if let Some(spec) = version.as_deref().filter(|_| check_ver) {
+                for tag in pinned_tags(spec) {
+                    if reuse(&path, &version_id(channel, &tag))? {
                         return Ok(false);
                     }
                 }
             }
-
-            let tarball = reqwest::get(
-                release
-                    .assets
-                    .iter()
-                    .find(|a| a.name == "dist.tar.gz")
-                    .unwrap()
-                    .browser_download_url
-                    .to_string(),
-            )
-            .await?
-            .bytes()
-            .await?;
-            let stable_ref = release.tag_name;
-            tar::Archive::new(flate2::read::GzDecoder::new(std::io::Cursor::new(tarball)))
-                .unpack(&path.join("dist"))?;
-
-            fs::write(path.join("dist/version.txt"), stable_ref)?;
-            fs::write(path.join("dist/branch.txt"), "stable")?;
-
-            l::info!(
-                "Downloaded moonlight (channel: {:?}) in {}ms",
-                channel,
-                stopwatch.elapsed().as_millis()
-            );
-            Ok(false)
-        }
-        Channel::Nightly => {
-            let nightly_ref = reqwest::get("https://moonlight-mod.github.io/moonlight/ref")
-                .await?
-                .text()
-                .await?
-                .split("\n")
-                .next()
-                .unwrap()
-                .to_owned();
-            if check_ver {
-                if path.join("dist/version.txt").exists() {
-                    if fs::read_to_string(path.join("dist/version.txt"))? == nightly_ref {
-                        l::info!("Moonlight is up to date");
-                        return Ok(false);
-                    }
-                }
+            let published = Published::resolve(channel, version.as_deref()).await?;
+            let id = version_id(channel, &published.version);
+            if check_ver && reuse(&path, &id)? {
+                return Ok(false);
             }
-            let tarball = reqwest::get("https://moonlight-mod.github.io/moonlight/dist.tar.gz")
-                .await?
-                .bytes()
-                .await?;
-            tar::Archive::new(flate2::read::GzDecoder::new(std::io::Cursor::new(tarball)))
-                .unpack(&path.join("dist"))?;
 
-            fs::write(path.join("dist/version.txt"), nightly_ref)?;
-            fs::write(path.join("dist/branch.txt"), "nightly")?;
+            let (tarball, sha256) = published.fetch().await?;
+            unpack(&path, &id, &tarball)?.commit(channel, &published.version, Some(&sha256))?;
+            prune_versions(&path);
 
             l::info!(
                 "Downloaded moonlight (channel: {:?}) in {}ms",
@@ -101,58 +339,66 @@ pub async fn download(
             );
             Ok(false)
         }
+        Channel::Local => {
+            Err("A dist installed from disk can't be downloaded again, use moon up --from".into())
+        }
         Channel::Git => {
+            // the checkout lives beside the versions, only its build is kept
+            let checkout = path.join("git");
             {
-                if check_ver && path.join(".git").exists() {
-                    let reference = git2::Repository::open(path.join(".git"))?
+                if check_ver && checkout.join(".git").exists() {
+                    let reference = git2::Repository::open(checkout.join(".git"))?
                         .head()?
                         .target()
                         .unwrap();
-                    if path.join("dist/version.txt").exists() {
-                        if fs::read_to_string(path.join("dist/version.txt"))?
-                            == reference.to_string()
-                        {
-                            l::info!("Moonlight is up to date");
-                            return Ok(false);
-                        }
+                    if reuse(&path, &version_id(channel, &reference.to_string()))? {
+                        return Ok(false);
                     }
                 }
-                if path.exists() {
-                    fs::remove_dir_all(&path)?;
+                if checkout.exists() {
+                    fs::remove_dir_all(&checkout)?;
                 }
             };
-            git2::Repository::clone(
-                &repo_location.unwrap_or("https://github.com/moonlight-mod/moonlight".to_string()),
-                &path,
-            )?;
-
-            let mut pnpm = "pnpm".to_owned();
-            #[cfg(target_os = "windows")]
-            {
-                // find pnpm
-                pnpm = format!(
-                    "{}\\{}\\pnpm.cmd",
-                    env("APPDATA", &env("USERPROFILE", "./")),
-                    "npm"
-                );
+            let network = http::network()?;
+            let mut fetch = git2::FetchOptions::new();
+            if let Some(proxy) = &network.proxy {
+                let mut options = git2::ProxyOptions::new();
+                options.url(proxy);
+                fetch.proxy_options(options);
+            }
+            if let Some(bundle) = http::ca_bundle(network)? {
+                // libgit2 takes a single CA file for the whole process, so it gets
+                // the system's certificates and the extra ones combined
+                unsafe { git2::opts::set_ssl_cert_file(&bundle)? };
             }
+            let repo = git2::build::RepoBuilder::new()
+                .fetch_options(fetch)
+                .clone(
+                    &repo_location.unwrap_or(network.git_url().to_string()),
+                    &checkout,
+                )?;
+            let reference = repo.head()?.target().unwrap().to_string();
+
+            let pnpm = Platform::pnpm();
 
             if !Platform::cmd_is_ok(
                 vec![pnpm.clone(), "install".to_owned()],
-                Some(path.to_str().unwrap()),
+                Some(checkout.to_str().unwrap()),
             ) {
                 return Err("Failed to install dependencies".into());
             }
 
             if !Platform::cmd_is_ok(
                 vec![pnpm.clone(), "build".to_owned()],
-                Some(path.to_str().unwrap()),
+                Some(checkout.to_str().unwrap()),
             ) {
                 return Err("Failed to build Moonlight".into());
             }
 
-            fs::write(path.join("dist/branch.txt"), "git")?;
-            fs::write(path.join("dist/version.txt"), get_ref(Channel::Git).await)?; // TODO: make this not use await (it's not async, but it's in an async function
+            let staged = Staged::new(&path, &version_id(channel, &reference))?;
+            copy_dir(&checkout.join("dist"), staged.dir())?;
+            staged.commit(channel, &reference, None)?;
+            prune_versions(&path);
             l::info!(
                 "Downloaded moonlight (channel: {:?}) in {}ms",
                 channel,
@@ -163,38 +409,97 @@ pub async fn download(
     }
 }
 
-pub async fn get_ref(channel: Channel) -> String {
-    match channel {
-        Channel::Stable => {
-            octocrab::instance()
-                .repos("moonlight-mod", "moonlight")
-                .releases()
-                .get_latest()
-                .await
-                .unwrap()
-                .tag_name
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::test_dir;
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn parses_checksums() {
+        let listing = format!("{}  other.tar.gz\n{}  dist.tar.gz\n", "0".repeat(64), HASH);
+        assert_eq!(parse_checksum(&listing, "dist.tar.gz").as_deref(), Some(HASH));
+        let binary = format!("{} *dist.tar.gz", HASH.to_uppercase());
+        assert_eq!(parse_checksum(&binary, "dist.tar.gz").as_deref(), Some(HASH));
+        assert_eq!(parse_checksum(&format!("{}\n", HASH), "dist.tar.gz").as_deref(), Some(HASH));
+
+        assert_eq!(parse_checksum(&format!("{}  other.tar.gz", HASH), "dist.tar.gz"), None);
+        assert_eq!(parse_checksum(&HASH[1..], "dist.tar.gz"), None);
+        assert_eq!(parse_checksum(&HASH.replace('9', "g"), "dist.tar.gz"), None);
+        assert_eq!(parse_checksum("", "dist.tar.gz"), None);
+    }
+
+    #[test]
+    fn only_relative_paths_are_contained() {
+        assert!(contained(Path::new("injector.js")));
+        assert!(contained(Path::new("./core/index.js")));
+        assert!(!contained(Path::new("../injector.js")));
+        assert!(!contained(Path::new("core/../../injector.js")));
+        assert!(!contained(Path::new("/etc/passwd")));
+    }
+
+    /// A dist tarball of `(path, type, link target)` entries, written
+    /// straight into the headers so they can name paths `tar` won't build.
+    fn tarball(entries: &[(&str, tar::EntryType, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::fast(),
+        ));
+        for (path, kind, link) in entries {
+            let data = if *kind == tar::EntryType::Regular { b"moon".as_slice() } else { b"" };
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
         }
-        Channel::Nightly => reqwest::get("https://moonlight-mod.github.io/moonlight/ref")
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap()
-            .split("\n")
-            .next()
-            .unwrap()
-            .to_owned(),
-        Channel::Git => {
-            match octocrab::instance()
-                .repos("moonlight-mod", "moonlight")
-                .get_ref(&Reference::Branch("main".to_string()))
-                .await
-                .unwrap()
-                .object
-            {
-                Object::Commit { sha, .. } | Object::Tag { sha, .. } => sha,
-                _ => panic!("Invalid object type"),
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn unpacks_a_dist() {
+        let root = test_dir("unpack-dist");
+        let staged = unpack(
+            &root,
+            "dist",
+            &tarball(&[
+                ("injector.js", tar::EntryType::Regular, None),
+                ("core/index.js", tar::EntryType::Regular, None),
+                ("core/injector.js", tar::EntryType::Symlink, Some("../injector.js")),
+                ("core/hard.js", tar::EntryType::Link, Some("core/index.js")),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(fs::read(staged.dir().join("injector.js")).unwrap(), b"moon");
+        assert_eq!(fs::read(staged.dir().join("core/hard.js")).unwrap(), b"moon");
+        #[cfg(unix)]
+        assert_eq!(fs::read(staged.dir().join("core/injector.js")).unwrap(), b"moon");
+    }
+
+    #[test]
+    fn refuses_escaping_entries() {
+        let root = test_dir("unpack-escapes");
+        let escapes: [&[(&str, tar::EntryType, Option<&str>)]; 6] = [
+            &[("../injector.js", tar::EntryType::Regular, None)],
+            &[("core/../../injector.js", tar::EntryType::Regular, None)],
+            &[("/tmp/injector.js", tar::EntryType::Regular, None)],
+            &[("core/injector.js", tar::EntryType::Symlink, Some("../../x"))],
+            &[("injector.js", tar::EntryType::Symlink, Some("/etc/passwd"))],
+            &[("injector.js", tar::EntryType::Link, Some("../x"))],
+        ];
+        for entries in escapes {
+            match unpack(&root, "dist", &tarball(entries)) {
+                Err(e) => assert!(e.to_string().contains("outside of it"), "{:?}: {}", entries, e),
+                Ok(_) => panic!("{:?} unpacked", entries),
             }
         }
+        assert!(!root.parent().unwrap().join("x").exists());
+        assert!(!root.parent().unwrap().join("injector.js").exists());
     }
 }
@@ -0,0 +1,115 @@
+use log as l;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::lock::Lock;
+use crate::platform::{copy_dir, sha256_bytes, sha256_file};
+
+use super::download::{check_digest, parse_checksum, prune_versions, unpack};
+use super::versions::{clear_staging, migrate_legacy, Staged};
+use super::{read_channel, read_version, Channel, DIST_FILES};
+
+/// The channel and version a dist from disk is recorded as: `recorded` if
+/// the caller knows, whatever its own branch.txt and version.txt say, else a
+/// local build named by its hash.
+#[inline(always)]
+fn metadata(dist: &Path, hash: &str, recorded: &Option<(Channel, String)>) -> (Channel, String) {
+    if let Some(recorded) = recorded {
+        return recorded.clone();
+    }
+    match (read_channel(dist), read_version(dist)) {
+        (Some(channel), Some(version)) => (channel, version),
+        _ => (Channel::Local, hash[..12].to_string()),
+    }
+}
+
+/// Refuses a dist of another channel than the one `root` is configured for,
+/// before it's committed over what's there.
+#[inline(always)]
+fn check_channel(
+    from: &Path,
+    dist: Channel,
+    expected: Option<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    match expected {
+        Some(expected) if expected != dist => Err(format!(
+            "{:?} holds a {:?} dist, but the branch is configured for {:?}, supply --channel to switch it",
+            from, dist, expected
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Installs the dist at `from` into `root` without touching the network.
+/// `from` is either a dist.tar.gz, checked against a `<from>.sha256` beside
+/// it if there is one, or a folder holding an unpacked dist. `recorded` is
+/// the channel and version it came from, when known, and `expected` the
+/// channel `root` is configured for, if any.
+pub async fn install_local(
+    from: &Path,
+    root: &Path,
+    recorded: Option<(Channel, String)>,
+    expected: Option<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let _lock = Lock::acquire(root).await?;
+    fs::create_dir_all(root)?;
+    migrate_legacy(root)?;
+    clear_staging(root)?;
+
+    if from.is_dir() {
+        if let Some(file) = DIST_FILES.iter().find(|file| !from.join(file).is_file()) {
+            return Err(format!("{:?} isn't a moonlight dist, it has no {}", from, file).into());
+        }
+        let (channel, version) = metadata(
+            from,
+            &sha256_file(&from.join("injector.js"))?,
+            &recorded,
+        );
+        check_channel(from, channel, expected)?;
+        let staged = Staged::new(root, "local")?;
+        copy_dir(from, staged.dir())?;
+        l::info!(
+            "Installing moonlight ({:?} {}) from {:?}",
+            channel,
+            version,
+            from
+        );
+        staged.commit(channel, &version, None)?;
+    } else {
+        let tarball = fs::read(from)?;
+        let mut checksum = from.as_os_str().to_owned();
+        checksum.push(".sha256");
+        let sha256 = match fs::read_to_string(PathBuf::from(checksum)) {
+            Ok(text) => {
+                let name = from.file_name().unwrap_or_default().to_string_lossy();
+                let expected = parse_checksum(&text, &name).ok_or_else(|| {
+                    format!("The checksum file beside {:?} holds no SHA-256", from)
+                })?;
+                check_digest(&tarball, &expected)?
+            }
+            Err(_) => {
+                l::warn!(
+                    "No checksum file beside {:?}, installing it unverified",
+                    from
+                );
+                sha256_bytes(&tarball)
+            }
+        };
+        let staged = unpack(root, "local", &tarball)?;
+        let (channel, version) = metadata(staged.dir(), &sha256, &recorded);
+        check_channel(from, channel, expected)?;
+        l::info!(
+            "Installing moonlight ({:?} {}) from {:?}",
+            channel,
+            version,
+            from
+        );
+        staged.commit(channel, &version, Some(&sha256))?;
+    }
+    prune_versions(root);
+    Ok(())
+}
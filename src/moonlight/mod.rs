@@ -1,12 +1,59 @@
+mod cache;
 mod channel;
 mod download;
+mod local;
+pub mod versions;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub use channel::Channel;
-pub use download::{download};
+pub use download::{download, Published};
+pub use local::install_local;
 
-pub async fn init_moonlight(at: PathBuf, channel: Option<Channel>, repo_location: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+/// Files a usable dist/ has to contain, injector.js loads the other two.
+pub const DIST_FILES: &[&str] = &["injector.js", "node-preload.js", "web-preload.js"];
+
+/// The channel recorded in a dist folder's branch.txt.
+#[inline(always)]
+pub(crate) fn read_channel(dist: &Path) -> Option<Channel> {
+    Channel::parse(&std::fs::read_to_string(dist.join("branch.txt")).ok()?)
+}
+
+/// The version recorded in a dist folder's version.txt.
+#[inline(always)]
+pub(crate) fn read_version(dist: &Path) -> Option<String> {
+    Some(std::fs::read_to_string(dist.join("version.txt")).ok()?.trim().to_string())
+}
+
+/// The channel the active dist under `root` was installed from.
+pub fn recorded_channel(root: &Path) -> Option<Channel> {
+    read_channel(&versions::active_dist(root))
+}
+
+/// Checks the active dist under `root` is complete, and that dist/injector.js
+/// still leads to it, returning the channel and version it was installed from.
+pub fn verify_dist(root: &Path) -> Result<(Channel, String), String> {
+    let dist = versions::active_dist(root);
+    let channel = match read_channel(&dist) {
+        Some(channel) => channel,
+        None => return Err(format!("no valid branch.txt in {:?}", dist)),
+    };
+    let version = match read_version(&dist) {
+        Some(version) => version,
+        None => return Err(format!("no version.txt in {:?}", dist)),
+    };
+    for file in DIST_FILES {
+        if !dist.join(file).is_file() {
+            return Err(format!("{} is missing from {:?} ({:?} {})", file, dist, channel, version));
+        }
+    }
+    if !root.join("dist").join("injector.js").is_file() {
+        return Err(format!("{:?} is missing", root.join("dist").join("injector.js")));
+    }
+    Ok((channel, version))
+}
+
+pub async fn init_moonlight(at: PathBuf, channel: Option<Channel>, repo_location: Option<String>, version: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
     let channel = channel.unwrap_or({
         if at.join("branch.txt").exists() {
             match std::fs::read_to_string(at.join("branch.txt"))?.as_str() {
@@ -15,19 +62,14 @@ pub async fn init_moonlight(at: PathBuf, channel: Option<Channel>, repo_location
                 "git" => Channel::Git,
                 _ => Channel::Stable
             }
-        } else if at.join("dist").join("branch.txt").exists() {
-            match std::fs::read_to_string(at.join("dist").join("branch.txt"))?.as_str() {
-                "stable" => Channel::Stable,
-                "nightly" => Channel::Nightly,
-                "git" => Channel::Git,
-                _ => Channel::Stable
-            }
+        } else if let Some(channel) = recorded_channel(&at) {
+            channel
         } else {
             Channel::Stable
         }
     });
 
-    download(channel, at, repo_location, true).await?;
+    download(channel, at, repo_location, version, true).await?;
 
     Ok(())
 }
\ No newline at end of file
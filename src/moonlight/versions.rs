@@ -0,0 +1,346 @@
+use log as l;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::platform::sha256_file;
+
+use super::{read_channel, read_version, Channel, DIST_FILES};
+
+/// Names the active version under `versions/`, the shim loads whatever it says.
+const ACTIVE: &str = "active";
+/// Names the version that was active before, what `moon rollback` goes back to.
+const PREVIOUS: &str = "previous";
+
+const SHIM_MARKER: &str =
+    "// Managed by moon, loads the active version under ../versions, see `moon rollback`\n";
+const SHIM_BODY: &str = r#"const path = require("path");
+const root = path.resolve(__dirname, "..");
+const active = require("fs").readFileSync(path.join(root, "active"), "utf8").trim();
+module.exports = require(path.join(root, "versions", active, "injector.js"));
+"#;
+
+/// One dist kept under `<root>/versions`.
+#[derive(Debug, Clone)]
+pub struct Version {
+    /// The folder name, `<channel>-<version>`.
+    pub id: String,
+    pub version: Option<String>,
+    pub installed: Option<SystemTime>,
+}
+
+#[inline(always)]
+pub fn versions_dir(root: &Path) -> PathBuf {
+    root.join("versions")
+}
+
+/// The folder name a dist of `version` from `channel` is kept under.
+#[inline(always)]
+pub fn version_id(channel: Channel, version: &str) -> String {
+    let version = version
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("{}-{}", channel.name(), version)
+}
+
+#[inline(always)]
+fn read_pointer(root: &Path, name: &str) -> Option<String> {
+    let id = fs::read_to_string(root.join(name)).ok()?.trim().to_string();
+    (!id.is_empty()).then_some(id)
+}
+
+/// Writes then renames, so a pointer is never seen half written.
+#[inline(always)]
+fn write_atomic(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[inline(always)]
+pub fn active_version(root: &Path) -> Option<String> {
+    read_pointer(root, ACTIVE)
+}
+
+#[inline(always)]
+pub fn previous_version(root: &Path) -> Option<String> {
+    read_pointer(root, PREVIOUS)
+}
+
+/// The folder holding the dist `<root>/dist/injector.js` ends up loading:
+/// the active version, or dist/ itself in roots from before versioning.
+pub fn active_dist(root: &Path) -> PathBuf {
+    match active_version(root) {
+        Some(id) => versions_dir(root).join(id),
+        None => root.join("dist"),
+    }
+}
+
+/// Whether `root` is one moon set up: its dist/injector.js is moon's shim,
+/// or it has an active version that's really there.
+pub fn is_moon_root(root: &Path) -> bool {
+    let shim = fs::read_to_string(root.join("dist").join("injector.js"))
+        .map(|injector| injector.starts_with(SHIM_MARKER))
+        .unwrap_or(false);
+    shim || active_version(root)
+        .map(|id| versions_dir(root).join(id).is_dir())
+        .unwrap_or(false)
+}
+
+/// Every version kept under `root`, newest first.
+pub fn list_versions(root: &Path) -> Vec<Version> {
+    let mut versions = fs::read_dir(versions_dir(root))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .map(|e| {
+                    let dir = e.path();
+                    Version {
+                        id: e.file_name().to_string_lossy().to_string(),
+                        version: read_version(&dir),
+                        installed: fs::metadata(dir.join("version.txt"))
+                            .and_then(|m| m.modified())
+                            .ok(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    versions.sort_by_key(|v| std::cmp::Reverse(v.installed));
+    versions
+}
+
+/// Finds a kept version by folder name or by the version alone.
+pub fn find_version(root: &Path, name: &str) -> Option<Version> {
+    let versions = list_versions(root);
+    versions
+        .iter()
+        .find(|v| v.id == name)
+        .or_else(|| versions.iter().find(|v| v.version.as_deref() == Some(name)))
+        .cloned()
+}
+
+/// Moves a dist unpacked straight into dist/ by older versions of moon into
+/// `versions/`, so it can still be rolled back to. One without a version,
+/// like a hand built one, is kept as a local build named by its hash.
+pub fn migrate_legacy(root: &Path) -> Result<(), Box<dyn Error>> {
+    let dist = root.join("dist");
+    if root.join(ACTIVE).exists() || !dist.join("injector.js").is_file() {
+        return Ok(());
+    }
+    let (channel, version) = match (read_channel(&dist), read_version(&dist)) {
+        (Some(channel), Some(version)) => (channel, version),
+        _ => {
+            let hash = sha256_file(&dist.join("injector.js"))?;
+            let version = hash[..12].to_string();
+            fs::write(dist.join("version.txt"), &version)?;
+            fs::write(dist.join("branch.txt"), Channel::Local.name())?;
+            (Channel::Local, version)
+        }
+    };
+    let id = version_id(channel, &version);
+    let to = versions_dir(root).join(&id);
+    fs::create_dir_all(versions_dir(root))?;
+    if to.exists() {
+        fs::remove_dir_all(&dist)?;
+    } else {
+        l::info!(
+            "Keeping the existing dist ({:?} {}) as {}",
+            channel,
+            version,
+            id
+        );
+        fs::rename(&dist, &to)?;
+    }
+    write_atomic(&root.join(ACTIVE), &id)?;
+    Ok(())
+}
+
+/// Makes `id` the version Discord loads from now on, remembering the one it
+/// replaces for `moon rollback`.
+pub fn activate(root: &Path, id: &str) -> Result<(), Box<dyn Error>> {
+    if !versions_dir(root).join(id).is_dir() {
+        return Err(format!("No version {} under {:?}", id, versions_dir(root)).into());
+    }
+    migrate_legacy(root)?;
+
+    let dist = root.join("dist");
+    fs::create_dir_all(&dist)?;
+    let shim = format!("{}{}", SHIM_MARKER, SHIM_BODY);
+    if fs::read_to_string(dist.join("injector.js")).ok().as_deref() != Some(shim.as_str()) {
+        write_atomic(&dist.join("injector.js"), &shim)?;
+    }
+
+    let old = active_version(root);
+    if old.as_deref() == Some(id) {
+        return Ok(());
+    }
+    write_atomic(&root.join(ACTIVE), id)?;
+    if let Some(old) = old {
+        write_atomic(&root.join(PREVIOUS), &old)?;
+    }
+    l::info!("Activated moonlight {}", id);
+    Ok(())
+}
+
+/// Drops all but the `keep` newest versions. The active and previous ones
+/// are always kept on top of those.
+pub fn prune(root: &Path, keep: usize) -> Result<(), Box<dyn Error>> {
+    let active = active_version(root);
+    let previous = previous_version(root);
+    for version in list_versions(root)
+        .into_iter()
+        .filter(|v| Some(&v.id) != active.as_ref() && Some(&v.id) != previous.as_ref())
+        .skip(keep)
+    {
+        l::info!("Removing old moonlight {}", version.id);
+        fs::remove_dir_all(versions_dir(root).join(&version.id))?;
+    }
+    Ok(())
+}
+
+/// Where dists are put together before they're moved into `versions/`.
+#[inline(always)]
+fn staging_dir(root: &Path) -> PathBuf {
+    root.join("staging")
+}
+
+/// Removes whatever an interrupted download left staged. Only call this
+/// with the root locked, or another run's staged dist goes with it.
+pub fn clear_staging(root: &Path) -> Result<(), Box<dyn Error>> {
+    if staging_dir(root).exists() {
+        l::debug!("Clearing {:?}", staging_dir(root));
+        fs::remove_dir_all(staging_dir(root))?;
+    }
+    Ok(())
+}
+
+/// A dist being unpacked or copied under `<root>/staging`, away from the
+/// versions Discord may be loading. It only becomes a version once it's
+/// been checked over by `commit`, and is removed again if dropped before.
+pub struct Staged {
+    root: PathBuf,
+    dir: PathBuf,
+    committed: bool,
+}
+
+impl Staged {
+    /// `name` only names the folder it's staged in, its version decides
+    /// where it goes once committed.
+    pub fn new(root: &Path, name: &str) -> Result<Self, Box<dyn Error>> {
+        let dir = staging_dir(root).join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            dir,
+            committed: false,
+        })
+    }
+
+    #[inline(always)]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Checks the staged dist is complete, then moves it into `versions/`
+    /// and activates it. Until the final rename nothing Discord loads has
+    /// changed, so a failure here leaves the active version as it was.
+    /// `sha256` is the verified hash of the tarball it came from, if any.
+    pub fn commit(
+        mut self,
+        channel: Channel,
+        version: &str,
+        sha256: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::write(self.dir.join("version.txt"), version)?;
+        fs::write(self.dir.join("branch.txt"), channel.name())?;
+        if let Some(sha256) = sha256 {
+            fs::write(self.dir.join("sha256.txt"), sha256)?;
+        }
+        for file in DIST_FILES {
+            match fs::metadata(self.dir.join(file)) {
+                Ok(meta) if meta.is_file() && meta.len() > 0 => {}
+                _ => {
+                    return Err(format!(
+                        "The new dist ({:?} {}) has no usable {}",
+                        channel, version, file
+                    )
+                    .into())
+                }
+            }
+        }
+
+        let id = version_id(channel, version);
+        let to = versions_dir(&self.root).join(&id);
+        fs::create_dir_all(versions_dir(&self.root))?;
+        if to.exists() {
+            // a kept version is only staged again to repair it or to reinstall
+            // it from disk, so it's swapped out whole
+            let old = staging_dir(&self.root).join(format!("{}.old", id));
+            if old.exists() {
+                fs::remove_dir_all(&old)?;
+            }
+            fs::rename(&to, &old)?;
+            fs::rename(&self.dir, &to)?;
+            if let Err(e) = fs::remove_dir_all(&old) {
+                l::warn!("Failed to remove {:?}: {}", old, e);
+            }
+        } else {
+            fs::rename(&self.dir, &to)?;
+        }
+        self.committed = true;
+        activate(&self.root, &id)
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if !self.committed && self.dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.dir) {
+                l::warn!("Failed to remove the staged dist at {:?}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::test_dir;
+
+    #[test]
+    fn only_roots_moon_set_up_count() {
+        let root = test_dir("versions-moon-root");
+        fs::create_dir_all(root.join("versions/stable-1.0.0")).unwrap();
+        activate(&root, "stable-1.0.0").unwrap();
+        assert!(is_moon_root(&root));
+
+        fs::remove_file(root.join("dist/injector.js")).unwrap();
+        assert!(is_moon_root(&root));
+        fs::remove_dir_all(root.join("versions/stable-1.0.0")).unwrap();
+        assert!(!is_moon_root(&root));
+
+        let project = test_dir("versions-project");
+        fs::create_dir_all(project.join("dist")).unwrap();
+        fs::create_dir_all(project.join("versions")).unwrap();
+        fs::write(project.join("dist/injector.js"), "module.exports = {};").unwrap();
+        assert!(!is_moon_root(&project));
+    }
+}
@@ -0,0 +1,366 @@
+use log as l;
+use std::{error::Error, fmt, fs, path::{Path, PathBuf}};
+
+use crate::discord::{
+    download_openasar, Backup, DiscordInstall, DiscordKind, ForeignPolicy, InjectorFormat,
+    Journal, Operation, Step,
+};
+use crate::lock::Lock;
+use crate::moonlight::{init_moonlight, install_local, Channel};
+use crate::platform::move_dir;
+use crate::state::State;
+
+/// Something a command is going to do to the machine.
+#[derive(Debug)]
+pub enum Action<'a> {
+    /// Back up Discord's original app.asar into moon's data dir, if needed.
+    Backup(&'a DiscordInstall),
+    /// Like `Backup`, but stops the plan if there's no good backup after,
+    /// for when Discord's asar is about to be replaced.
+    EnsureBackup(&'a DiscordInstall),
+    Kill(&'a DiscordInstall),
+    /// Bring the dist under `root` up to date, if needed. `version` pins a
+    /// stable release instead of taking the latest.
+    Download {
+        channel: Channel,
+        root: PathBuf,
+        version: Option<String>,
+    },
+    /// Install the dist.tar.gz or unpacked dist at `from` into `root`,
+    /// recorded as the given channel and version if known. With a `channel`,
+    /// the root's branch is configured for it and only a dist of it goes in.
+    InstallLocal {
+        from: PathBuf,
+        root: PathBuf,
+        recorded: Option<(Channel, String)>,
+        channel: Option<Channel>,
+    },
+    DownloadOpenAsar(PathBuf),
+    /// Move a whole moonlight root, copying if it has to cross filesystems.
+    Move { from: PathBuf, to: PathBuf },
+    /// Delete a file or folder moon made, for good.
+    Delete(PathBuf),
+    /// Filesystem steps run as one journaled transaction.
+    Transaction {
+        install: &'a DiscordInstall,
+        operation: Operation,
+        steps: Vec<Step>,
+    },
+}
+
+impl fmt::Display for Action<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Backup(install) => write!(
+                f,
+                "back up app.asar of Discord {:?} at {:?}",
+                install.kind, install.path
+            ),
+            Action::EnsureBackup(install) => write!(
+                f,
+                "back up app.asar of Discord {:?} at {:?}, stopping if that's not possible",
+                install.kind, install.path
+            ),
+            Action::Kill(install) => write!(f, "kill Discord {:?}", install.kind),
+            Action::Download {
+                channel,
+                root,
+                version: None,
+            } => write!(
+                f,
+                "download moonlight ({:?}) into {:?} if it's out of date",
+                channel, root
+            ),
+            Action::Download {
+                channel,
+                root,
+                version: Some(version),
+            } => write!(
+                f,
+                "download moonlight ({:?} {}) into {:?} if it's not there yet",
+                channel, version, root
+            ),
+            Action::InstallLocal { from, root, .. } => {
+                write!(f, "install moonlight from {:?} into {:?}", from, root)
+            }
+            Action::DownloadOpenAsar(to) => write!(f, "download OpenAsar to {:?}", to),
+            Action::Move { from, to } => write!(f, "move {:?} to {:?}", from, to),
+            Action::Delete(path) => write!(f, "delete {:?}", path),
+            Action::Transaction {
+                install,
+                operation,
+                steps,
+            } => {
+                write!(
+                    f,
+                    "{:?} Discord {:?} at {:?}",
+                    operation, install.kind, install.path
+                )?;
+                for step in steps {
+                    write!(f, "\n       {}", step)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Everything a command will do, in order. Built up front so it can be
+/// printed with `--dry-run`, the same plan is what gets executed otherwise.
+#[derive(Debug, Default)]
+pub struct Plan<'a> {
+    pub actions: Vec<Action<'a>>,
+}
+
+impl<'a> Plan<'a> {
+    #[inline(always)]
+    pub fn push(&mut self, action: Action<'a>) {
+        self.actions.push(action);
+    }
+
+    pub fn inject(
+        &mut self,
+        install: &'a DiscordInstall,
+        moonlight_root: &Path,
+        policy: ForeignPolicy,
+    ) -> Result<(), Box<dyn Error>> {
+        let steps = install.plan_inject(moonlight_root, policy)?;
+        self.push(Action::Backup(install));
+        self.push(Action::Transaction {
+            install,
+            operation: Operation::Inject,
+            steps,
+        });
+        Ok(())
+    }
+
+    /// Copies `extensions`, as `(id, asar)`, into the moonlight extensions
+    /// folder of `install`. Any the user already has are parked until the
+    /// transaction commits, and it's all part of the injection just planned
+    /// for `install`, so a failure undoes the lot.
+    pub fn extensions(&mut self, install: &'a DiscordInstall, extensions: Vec<(String, PathBuf)>) {
+        if extensions.is_empty() {
+            return;
+        }
+        let dir = install.moonlight_extensions_dir();
+        let mut steps = vec![Step::CreateDir {
+            path: dir.clone(),
+            existed: false,
+        }];
+        for (id, asar) in extensions {
+            let to = dir.join(format!("{}.asar", id));
+            if to.exists() {
+                steps.push(Step::Remove {
+                    trash: Step::trash_for(&to),
+                    path: to.clone(),
+                });
+            }
+            steps.push(Step::Copy {
+                from: asar,
+                to,
+                existed: false,
+            });
+        }
+        match self.actions.last_mut() {
+            Some(Action::Transaction {
+                install: injected,
+                operation: Operation::Inject,
+                steps: injection,
+            }) if injected.path == install.path => injection.extend(steps),
+            _ => self.push(Action::Transaction {
+                install,
+                operation: Operation::Inject,
+                steps,
+            }),
+        }
+    }
+
+    pub fn uninject(&mut self, install: &'a DiscordInstall) -> Result<(), Box<dyn Error>> {
+        let steps = install.plan_uninject()?;
+        if !steps.is_empty() {
+            self.push(Action::Transaction {
+                install,
+                operation: Operation::Uninject,
+                steps,
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces Discord's asar with the OpenAsar at `openasar`. Discord's
+    /// own is backed up first, unless OpenAsar is only being updated.
+    pub fn openasar_up(&mut self, install: &'a DiscordInstall, openasar: &Path) {
+        if !install.is_openasar {
+            self.push(Action::EnsureBackup(install));
+        }
+        self.push(Action::Transaction {
+            install,
+            operation: Operation::InstallOpenAsar,
+            steps: install.plan_openasar_up(openasar),
+        });
+    }
+
+    pub fn openasar_down(&mut self, install: &'a DiscordInstall) -> Result<(), Box<dyn Error>> {
+        let steps = install.plan_openasar_down()?;
+        self.push(Action::Transaction {
+            install,
+            operation: Operation::RemoveOpenAsar,
+            steps,
+        });
+        Ok(())
+    }
+
+    /// Points an injected install at `moonlight_root`, adopting it first if
+    /// something other than moon injected it.
+    pub fn retarget(
+        &mut self,
+        install: &'a DiscordInstall,
+        moonlight_root: &PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        if install.injector().map(|i| i.format) == Some(InjectorFormat::Moon) {
+            self.push(Action::Transaction {
+                install,
+                operation: Operation::Retarget,
+                steps: install.plan_retarget(moonlight_root),
+            });
+        } else {
+            let steps = install.plan_adopt(Some(moonlight_root))?;
+            self.push(Action::Backup(install));
+            self.push(Action::Transaction {
+                install,
+                operation: Operation::Adopt,
+                steps,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        if self.actions.is_empty() {
+            l::info!("Nothing to do");
+            return;
+        }
+        l::info!("Planned actions:");
+        for (i, action) in self.actions.iter().enumerate() {
+            l::info!("  {:>2}. {}", i + 1, action);
+        }
+    }
+
+    /// Runs every action in order, stopping at the first failure.
+    pub async fn execute(self) -> Result<(), Box<dyn Error>> {
+        for action in self.actions {
+            l::debug!("Running: {}", action);
+            match action {
+                Action::Backup(install) => {
+                    if let Err(e) = Backup::create(install) {
+                        l::warn!("Failed to back up the original app.asar: {}", e);
+                    }
+                }
+                Action::EnsureBackup(install) => {
+                    Backup::create(install)?;
+                    match Backup::find(install) {
+                        Some(backup) if backup.verify()? => {}
+                        _ => {
+                            return Err(format!(
+                                "No good backup of Discord {:?}'s own app.asar, not replacing it",
+                                install.kind
+                            )
+                            .into())
+                        }
+                    }
+                }
+                Action::Kill(install) => {
+                    l::info!("Killing discord...");
+                    while install.kill().await.is_ok() {}
+                }
+                Action::Download {
+                    channel,
+                    root,
+                    version,
+                } => {
+                    l::info!("Making sure moonlight is up to date...");
+                    let result = init_moonlight(root.clone(), Some(channel), None, version).await;
+                    State::record_update(&root, &result).await;
+                    result?;
+                }
+                Action::InstallLocal {
+                    from,
+                    root,
+                    recorded,
+                    channel,
+                } => {
+                    let result = install_local(&from, &root, recorded, channel).await;
+                    State::record_update(&root, &result).await;
+                    result?;
+                }
+                Action::DownloadOpenAsar(to) => download_openasar(&to).await?,
+                Action::Move { from, to } => {
+                    let _from = Lock::acquire(&from).await?;
+                    let _to = Lock::acquire(&to).await?;
+                    l::info!("Moving {:?} to {:?}...", from, to);
+                    move_dir(&from, &to)?;
+                }
+                Action::Delete(path) => {
+                    if path.is_dir() {
+                        fs::remove_dir_all(&path)?;
+                    } else {
+                        fs::remove_file(&path)?;
+                    }
+                    l::info!("Removed {:?}", path);
+                }
+                Action::Transaction {
+                    install,
+                    operation,
+                    steps,
+                } => {
+                    l::info!("{:?} Discord {:?}...", operation, install.kind);
+                    Journal::run(install.kind, &install.path, operation, steps).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How each install fared when a command ran over several of them.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub results: Vec<(DiscordKind, PathBuf, Result<(), String>)>,
+    /// Installs left out on purpose, with why.
+    pub skipped: Vec<(DiscordKind, PathBuf, String)>,
+}
+
+impl Summary {
+    #[inline(always)]
+    pub fn record(&mut self, install: &DiscordInstall, result: Result<(), Box<dyn Error>>) {
+        self.results.push((
+            install.kind,
+            install.path.clone(),
+            result.map_err(|e| e.to_string()),
+        ));
+    }
+
+    #[inline(always)]
+    pub fn skip(&mut self, install: &DiscordInstall, reason: &str) {
+        self.skipped
+            .push((install.kind, install.path.clone(), reason.to_string()));
+    }
+
+    #[inline(always)]
+    pub fn ok(&self) -> bool {
+        self.results.iter().all(|(_, _, result)| result.is_ok())
+    }
+
+    pub fn print(&self) {
+        l::info!("Summary:");
+        for (kind, path, result) in &self.results {
+            match result {
+                Ok(_) => l::info!("  [ok]   Discord {:?} at {:?}", kind, path),
+                Err(e) => l::error!("  [fail] Discord {:?} at {:?}: {}", kind, path, e),
+            }
+        }
+        for (kind, path, reason) in &self.skipped {
+            l::info!("  [skip] Discord {:?} at {:?}: {}", kind, path, reason);
+        }
+    }
+}
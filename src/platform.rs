@@ -21,6 +21,28 @@ impl Platform {
         #[cfg(target_os = "linux")]
         return format!("{}/{}", env("HOME", "~/"), ".config/moon");
     }
+    /// Where moon keeps its own bookkeeping (journals, backups, state), kept
+    /// apart from the moonlight root so moving or wiping one doesn't touch the other.
+    #[inline(always)]
+    pub fn data_dir() -> String {
+        if let Ok(dir) = std::env::var("MOON_DATA_DIR") {
+            return dir;
+        }
+        #[cfg(target_os = "windows")]
+        return format!("{}/{}", env("LOCALAPPDATA", &env("USERPROFILE", "./")), "Moon");
+        #[cfg(target_os = "macos")]
+        return format!(
+            "{}/{}",
+            env("HOME", "~/"),
+            "Library/Application Support/Moon CLI"
+        );
+        #[cfg(target_os = "linux")]
+        return format!(
+            "{}/{}",
+            env("XDG_DATA_HOME", &format!("{}/{}", env("HOME", "~/"), ".local/share")),
+            "moon"
+        );
+    }
     #[inline(always)]
     pub fn cmd_is_ok(mut parts: Vec<String>, cwd: Option<&str>) -> bool {
         use std::process::Command;
@@ -37,6 +59,84 @@ impl Platform {
         }
     }
 
+    #[inline(always)]
+    pub fn cmd_output(mut parts: Vec<String>, cwd: Option<&str>) -> Option<String> {
+        let mut cmd = Command::new(parts.remove(0));
+        cmd.stderr(Stdio::null());
+        cmd.args(parts);
+        if let Some(cwd) = cwd {
+            cmd.current_dir(cwd);
+        }
+        match cmd.output() {
+            Ok(out) if out.status.success() => Some(String::from_utf8_lossy(&out.stdout).to_string()),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn pnpm() -> String {
+        #[cfg(target_os = "windows")]
+        return format!(
+            "{}\\{}\\pnpm.cmd",
+            env("APPDATA", &env("USERPROFILE", "./")),
+            "npm"
+        );
+        #[cfg(not(target_os = "windows"))]
+        return "pnpm".to_owned();
+    }
+
+    #[cfg(target_os = "windows")]
+    #[inline(always)]
+    pub fn is_root() -> bool {
+        // elevation is checked by Windows itself when it matters
+        false
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[inline(always)]
+    pub fn is_root() -> bool {
+        Self::cmd_output(vec!["id".to_owned(), "-u".to_owned()], None)
+            .map(|uid| uid.trim() == "0")
+            .unwrap_or(false)
+    }
+
+    /// Free bytes on the filesystem holding `path`, or its closest existing parent.
+    #[cfg(target_os = "windows")]
+    #[inline(always)]
+    pub fn free_space(path: &std::path::Path) -> Option<u64> {
+        use windows::core::HSTRING;
+        use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+        let path = path.ancestors().find(|p| p.exists())?;
+        let mut free = 0u64;
+        unsafe {
+            GetDiskFreeSpaceExW(
+                &HSTRING::from(path.as_os_str()),
+                Some(&mut free as *mut u64),
+                None,
+                None,
+            )
+        }
+        .ok()?;
+        Some(free)
+    }
+
+    /// Free bytes on the filesystem holding `path`, or its closest existing parent.
+    #[cfg(not(target_os = "windows"))]
+    #[inline(always)]
+    pub fn free_space(path: &std::path::Path) -> Option<u64> {
+        let path = path.ancestors().find(|p| p.exists())?;
+        let out = Self::cmd_output(
+            vec![
+                "df".to_owned(),
+                "-Pk".to_owned(),
+                path.to_string_lossy().to_string(),
+            ],
+            None,
+        )?;
+        let kib = out.lines().nth(1)?.split_whitespace().nth(3)?.parse::<u64>().ok()?;
+        Some(kib * 1024)
+    }
+
     #[cfg(target_os = "windows")]
     #[inline(always)]
     pub fn installs_by_kind(kind: DiscordKind) -> Vec<DiscordInstall> {
@@ -137,6 +237,48 @@ impl Platform {
         Ok(cmd.spawn()?.wait()?)
     }
 
+    #[cfg(target_os = "windows")]
+    #[inline(always)]
+    pub fn win_findbyname(taskname: &str) -> bool {
+        use windows::Win32::Foundation::CloseHandle;
+        use windows::Win32::System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32First, Process32Next, PROCESSENTRY32,
+            TH32CS_SNAPPROCESS,
+        };
+
+        let process_snapshot = match unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) } {
+            Ok(handle) => handle,
+            _ => {
+                l::error!("Failed to create process snapshot handle");
+                return false;
+            }
+        };
+
+        let mut proc_entry: PROCESSENTRY32 = Default::default();
+        proc_entry.dwSize = std::mem::size_of::<PROCESSENTRY32>() as u32;
+
+        let mut found = false;
+        let mut more =
+            unsafe { Process32First(process_snapshot, &mut proc_entry as *mut PROCESSENTRY32) }
+                .is_ok();
+        while more {
+            let proc_name =
+                unsafe { std::ffi::CStr::from_ptr(proc_entry.szExeFile.as_ptr() as *mut i8) }
+                    .to_string_lossy()
+                    .into_owned();
+            if proc_name == taskname {
+                found = true;
+                break;
+            }
+            more = unsafe { Process32Next(process_snapshot, &mut proc_entry as *mut PROCESSENTRY32) }
+                .is_ok();
+        }
+        if let Err(e) = unsafe { CloseHandle(process_snapshot) } {
+            l::error!("Failed to close process snapshot handle: {}", e);
+        }
+        found
+    }
+
     #[cfg(target_os = "windows")]
     #[inline(always)]
     pub fn win_termbyname(taskname: String) -> bool {
@@ -224,3 +366,87 @@ impl Platform {
 pub fn env(key: &str, default: &str) -> String {
     std::env::var(key).unwrap_or_else(|_| default.to_string())
 }
+
+#[inline(always)]
+pub fn sha256_file(path: &std::path::Path) -> Result<String, Box<dyn Error>> {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[inline(always)]
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Renames `from` to `to`, falling back to copying and deleting when they're
+/// on different filesystems.
+pub fn move_dir(from: &std::path::Path, to: &std::path::Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    l::debug!("Can't rename {:?}, copying instead", from);
+    if let Err(e) = copy_dir(from, to) {
+        // a half copy would only make the next try fail on a non-empty `to`
+        if let Err(re) = std::fs::remove_dir_all(to) {
+            l::warn!("Failed to remove the partial copy at {:?}: {}", to, re);
+        }
+        return Err(e);
+    }
+    std::fs::remove_dir_all(from)?;
+    Ok(())
+}
+
+/// Copies the folder `from` to `to`, recreating symlinks as symlinks rather
+/// than following them.
+pub fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            let link = std::fs::read_link(entry.path())?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&link, &target)?;
+            #[cfg(windows)]
+            {
+                if entry.path().is_dir() {
+                    std::os::windows::fs::symlink_dir(&link, &target)?;
+                } else {
+                    std::os::windows::fs::symlink_file(&link, &target)?;
+                }
+            }
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// A fresh scratch folder for a test, with moon's data dir pointed into the
+/// test run's own temp folder so tests never touch the real one.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+    static DATA_DIR: std::sync::Once = std::sync::Once::new();
+    let base = std::env::temp_dir().join(format!("moon-test-{}", std::process::id()));
+    DATA_DIR.call_once(|| std::env::set_var("MOON_DATA_DIR", base.join("data")));
+    let dir = base.join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
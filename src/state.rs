@@ -0,0 +1,257 @@
+use log as l;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::discord::{DiscordKind, Operation};
+use crate::lock::Lock;
+use crate::moonlight::{verify_dist, Channel};
+use crate::Platform;
+
+/// How moon hooked moonlight into an install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strategy {
+    /// resources/app loads moonlight, which loads Discord's own _app.asar.
+    AppFolder,
+    /// As `AppFolder`, but moonlight loads another client mod kept around
+    /// with `--foreign chain` instead of Discord's asar.
+    Chain,
+}
+
+/// An install moon has injected and not uninjected since.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Managed {
+    pub kind: DiscordKind,
+    pub path: PathBuf,
+    pub strategy: Strategy,
+    pub root: PathBuf,
+    pub channel: Option<Channel>,
+    pub version: Option<String>,
+    /// Seconds since the unix epoch.
+    pub updated: u64,
+}
+
+/// What moon last did to the machine, kept in `state.json` in moon's data dir.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub installs: Vec<Managed>,
+}
+
+/// One line of `history.jsonl`, which is only ever appended to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Seconds since the unix epoch.
+    pub time: u64,
+    /// An `Operation`, `Update` for a moonlight download or `Rollback`.
+    pub action: String,
+    pub kind: Option<DiscordKind>,
+    pub install: Option<PathBuf>,
+    pub root: Option<PathBuf>,
+    pub channel: Option<Channel>,
+    pub version: Option<String>,
+    /// Set when the action failed.
+    pub error: Option<String>,
+}
+
+#[inline(always)]
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `time` as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_time(time: u64) -> String {
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (time / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let secs = time % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+impl State {
+    #[inline(always)]
+    pub fn path() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("state.json")
+    }
+
+    #[inline(always)]
+    pub fn history_path() -> PathBuf {
+        PathBuf::from(Platform::data_dir()).join("history.jsonl")
+    }
+
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(Self::path()) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Every event in the history, oldest first. Lines that don't parse
+    /// are skipped rather than failing the whole read.
+    pub fn history() -> Result<Vec<Event>, Box<dyn Error>> {
+        match fs::read_to_string(Self::history_path()) {
+            Ok(contents) => Ok(contents
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append(event: &Event) -> Result<(), Box<dyn Error>> {
+        let path = Self::history_path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        Ok(())
+    }
+
+    /// Records a finished transaction on an install. `injected` is the root
+    /// and strategy the install was left loading, if it was injected.
+    pub async fn record_transaction(
+        kind: DiscordKind,
+        install: &Path,
+        operation: Operation,
+        injected: Option<(PathBuf, Strategy)>,
+        result: &Result<(), Box<dyn Error>>,
+    ) {
+        let dist = injected
+            .as_ref()
+            .and_then(|(root, _)| verify_dist(root).ok());
+        let event = Event {
+            time: now(),
+            action: format!("{:?}", operation),
+            kind: Some(kind),
+            install: Some(install.to_path_buf()),
+            root: injected.as_ref().map(|(root, _)| root.clone()),
+            channel: dist.as_ref().map(|(channel, _)| *channel),
+            version: dist.as_ref().map(|(_, version)| version.clone()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        let _lock = match Lock::acquire(&Self::path()).await {
+            Ok(lock) => lock,
+            Err(e) => {
+                l::warn!("Failed to record {:?} in moon's history: {}", operation, e);
+                return;
+            }
+        };
+        let update = || -> Result<(), Box<dyn Error>> {
+            Self::append(&event)?;
+            if result.is_err() {
+                return Ok(());
+            }
+            let mut state = Self::load()?;
+            match (operation, injected) {
+                (Operation::Uninject | Operation::Restore, _) => {
+                    state.installs.retain(|m| m.path != install);
+                }
+                (_, Some((root, strategy))) => {
+                    state.installs.retain(|m| m.path != install);
+                    state.installs.push(Managed {
+                        kind,
+                        path: install.to_path_buf(),
+                        strategy,
+                        root,
+                        channel: event.channel,
+                        version: event.version.clone(),
+                        updated: event.time,
+                    });
+                }
+                _ => return Ok(()),
+            }
+            state.save()
+        };
+        if let Err(e) = update() {
+            l::warn!("Failed to record {:?} in moon's history: {}", operation, e);
+        }
+    }
+
+    /// Records a moonlight download into `root`.
+    #[inline(always)]
+    pub async fn record_update(root: &Path, result: &Result<(), Box<dyn Error>>) {
+        Self::record_dist("Update", root, result).await
+    }
+
+    /// Records `moon rollback` switching the active version in `root`.
+    #[inline(always)]
+    pub async fn record_rollback(root: &Path, result: &Result<(), Box<dyn Error>>) {
+        Self::record_dist("Rollback", root, result).await
+    }
+
+    /// Records a change to the dist under `root` as `action`, updating the
+    /// installs that load it.
+    async fn record_dist(action: &str, root: &Path, result: &Result<(), Box<dyn Error>>) {
+        let dist = verify_dist(root).ok();
+        let event = Event {
+            time: now(),
+            action: action.to_string(),
+            kind: None,
+            install: None,
+            root: Some(root.to_path_buf()),
+            channel: dist.as_ref().map(|(channel, _)| *channel),
+            version: dist.as_ref().map(|(_, version)| version.clone()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        let _lock = match Lock::acquire(&Self::path()).await {
+            Ok(lock) => lock,
+            Err(e) => {
+                l::warn!(
+                    "Failed to record the {} in moon's history: {}",
+                    action.to_lowercase(),
+                    e
+                );
+                return;
+            }
+        };
+        let update = || -> Result<(), Box<dyn Error>> {
+            Self::append(&event)?;
+            if result.is_err() {
+                return Ok(());
+            }
+            let mut state = Self::load()?;
+            for managed in state.installs.iter_mut().filter(|m| m.root == root) {
+                managed.channel = event.channel;
+                managed.version = event.version.clone();
+                managed.updated = event.time;
+            }
+            state.save()
+        };
+        if let Err(e) = update() {
+            l::warn!("Failed to record the {} in moon's history: {}", action.to_lowercase(), e);
+        }
+    }
+}